@@ -0,0 +1,66 @@
+//! Stages a tree of 10,000 files of about 1 KiB into a fresh repository, once on a single
+//! thread and once on every core, so the two times show what hashing in parallel gains.
+//! Run with `cargo bench -p pit`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use pit::Repository;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FOLDERS: usize = 100;
+const FILES_PER_FOLDER: usize = 100;
+
+fn create_tree(root: &Path) {
+    for folder in 0..FOLDERS {
+        let folder_path = root.join(format!("folder-{}", folder));
+        fs::create_dir_all(&folder_path).unwrap();
+        for file in 0..FILES_PER_FOLDER {
+            let line = format!("line of file {} in folder {}\n", file, folder);
+            fs::write(
+                folder_path.join(format!("file-{}.txt", file)),
+                line.repeat(32),
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn add_benchmark(c: &mut Criterion) {
+    let root: PathBuf = std::env::temp_dir().join(format!("pit-bench-add-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    create_tree(&root);
+
+    let cores = std::thread::available_parallelism().map_or(1, |x| x.get());
+    let mut group = c.benchmark_group("add");
+    group.sample_size(10);
+    for threads in [1, cores] {
+        let name = match threads {
+            1 => "10k files, sequential".to_string(),
+            _ => format!("10k files, {} threads", threads),
+        };
+        // add hashes and stores files on the rayon pool it runs in.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let _ = fs::remove_dir_all(root.join(".pit"));
+                    Repository::init(&root).unwrap()
+                },
+                |repository| pool.install(|| repository.add(&[".".to_string()]).unwrap()),
+                BatchSize::PerIteration,
+            )
+        });
+        if cores == 1 {
+            break;
+        }
+    }
+    group.finish();
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(benches, add_benchmark);
+criterion_main!(benches);
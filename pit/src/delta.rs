@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+/// Length of the blocks of the base looked up while building a delta.
+const BLOCK_SIZE: usize = 16;
+
+const INSERT: u8 = 0;
+const COPY: u8 = 1;
+
+/// Describes `target` as a list of instructions that copy ranges of `base` or insert new
+/// bytes. The delta starts with the length of both contents so it can be checked on apply.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&base[offset..offset + BLOCK_SIZE])
+            .or_insert(offset);
+    }
+
+    let mut delta: Vec<u8> = Vec::new();
+    write_varint(&mut delta, base.len() as u64);
+    write_varint(&mut delta, target.len() as u64);
+    let mut insert_start = 0;
+    let mut position = 0;
+    while position < target.len() {
+        let base_offset = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|x| blocks.get(x));
+        let Some(&base_offset) = base_offset else {
+            position += 1;
+            continue;
+        };
+        let mut length = BLOCK_SIZE;
+        while base_offset + length < base.len()
+            && position + length < target.len()
+            && base[base_offset + length] == target[position + length]
+        {
+            length += 1;
+        }
+
+        write_insert(&mut delta, &target[insert_start..position]);
+        delta.push(COPY);
+        write_varint(&mut delta, base_offset as u64);
+        write_varint(&mut delta, length as u64);
+        position += length;
+        insert_start = position;
+    }
+    write_insert(&mut delta, &target[insert_start..]);
+
+    delta
+}
+
+/// Rebuilds the target of `create_delta`. Returns None when the delta does not belong to
+/// `base` or is damaged.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    if read_varint(delta, &mut position)? != base.len() as u64 {
+        return None;
+    }
+    let target_length = read_varint(delta, &mut position)? as usize;
+    let mut target: Vec<u8> = Vec::with_capacity(target_length);
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+        match instruction {
+            INSERT => {
+                let length = read_varint(delta, &mut position)? as usize;
+                target.extend(delta.get(position..position.checked_add(length)?)?);
+                position += length;
+            }
+            COPY => {
+                let offset = read_varint(delta, &mut position)? as usize;
+                let length = read_varint(delta, &mut position)? as usize;
+                target.extend(base.get(offset..offset.checked_add(length)?)?);
+            }
+            _ => return None,
+        }
+    }
+
+    if target.len() != target_length {
+        return None;
+    }
+
+    Some(target)
+}
+
+fn write_insert(delta: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    delta.push(INSERT);
+    write_varint(delta, bytes.len() as u64);
+    delta.extend(bytes);
+}
+
+/// Writes `value` 7 bits at a time, the high bit telling whether more bytes follow.
+fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+fn read_varint(input: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *input.get(*position)?;
+        *position += 1;
+        if shift > 63 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = create_delta(base, target);
+        assert_eq!(apply_delta(base, &delta).as_deref(), Some(target));
+        delta
+    }
+
+    #[test]
+    fn rebuilds_the_target() {
+        let base: Vec<u8> = (0..4000u32)
+            .flat_map(|x| x.to_string().into_bytes())
+            .collect();
+        let mut target = base.clone();
+        target.splice(1000..1010, b"changed".iter().copied());
+        target.extend(b"appended");
+
+        let delta = round_trip(&base, &target);
+
+        assert!(delta.len() < 100, "delta of {} bytes", delta.len());
+    }
+
+    #[test]
+    fn handles_empty_and_unrelated_contents() {
+        round_trip(b"", b"");
+        round_trip(b"", b"new content");
+        round_trip(b"old content that is long enough", b"");
+        round_trip(
+            b"0123456789abcdef0123456789abcdef",
+            b"nothing in common at all, really",
+        );
+        round_trip(&[7; 100], &[7; 1000]);
+    }
+
+    #[test]
+    fn rejects_a_delta_of_another_base() {
+        let delta = create_delta(
+            b"base content, long enough to copy",
+            b"base content, longer",
+        );
+
+        assert_eq!(apply_delta(b"another base", &delta), None);
+        assert_eq!(
+            apply_delta(b"base content, long enough to copy", &delta[..3]),
+            None
+        );
+    }
+
+    #[test]
+    fn encodes_large_lengths() {
+        let mut encoded: Vec<u8> = Vec::new();
+        write_varint(&mut encoded, u64::MAX);
+        let mut position = 0;
+
+        assert_eq!(read_varint(&encoded, &mut position), Some(u64::MAX));
+        assert_eq!(position, encoded.len());
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NotARepository,
+    AlreadyInitialized,
+    NotADirectory(String),
+    ObjectNotFound(String),
+    CorruptObject(String),
+    CorruptPack(String),
+    UnexpectedObject {
+        hash: String,
+        expected: String,
+    },
+    BranchNotFound(String),
+    RevisionNotFound(String),
+    NoCommits,
+    MergeInProgress,
+    NoMergeInProgress,
+    UnresolvedConflicts(Vec<String>),
+    LocalChanges(Vec<String>),
+    UntrackedFiles(Vec<String>),
+    NotTracked(String),
+    AlreadyExists(String),
+    /// The files of a commit would have the same path as a file and as a folder.
+    FileAndFolder(String),
+    CorruptIndex(String),
+    FileChanged(String),
+    OldFormat(u32),
+    UnsupportedFormat(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::NotARepository => write!(f, "Pit file not present. Run pit init."),
+            Error::AlreadyInitialized => write!(f, "Pit file already exist. No action done."),
+            Error::NotADirectory(path) => {
+                write!(
+                    f,
+                    "The path {} provided for the pit file is not a folder",
+                    path
+                )
+            }
+            Error::ObjectNotFound(hash) => write!(f, "Object {} not found", hash),
+            Error::CorruptObject(hash) => write!(f, "Object {} is corrupted", hash),
+            Error::CorruptPack(path) => write!(f, "Pack index {} is corrupted", path),
+            Error::UnexpectedObject { hash, expected } => {
+                write!(f, "Object {} is not a {}", hash, expected)
+            }
+            Error::BranchNotFound(branch) => write!(f, "Branch {} does not exist", branch),
+            Error::RevisionNotFound(revision) => {
+                write!(f, "{} is not a branch or a commit", revision)
+            }
+            Error::NoCommits => write!(f, "There are no commit on one branch"),
+            Error::MergeInProgress => write!(
+                f,
+                "A merge is in progress. Finish it with pit merge --continue or pit merge --abort."
+            ),
+            Error::NoMergeInProgress => write!(f, "There is no merge in progress"),
+            Error::UnresolvedConflicts(paths) => {
+                write!(f, "These files still have conflict markers:")?;
+                for path in paths {
+                    write!(f, "\n  {}", path)?;
+                }
+                Ok(())
+            }
+            Error::LocalChanges(paths) => {
+                write!(f, "These files have changes that are not committed:")?;
+                for path in paths {
+                    write!(f, "\n  {}", path)?;
+                }
+                Ok(())
+            }
+            Error::UntrackedFiles(paths) => {
+                write!(f, "These untracked files would be overwritten:")?;
+                for path in paths {
+                    write!(f, "\n  {}", path)?;
+                }
+                Ok(())
+            }
+            Error::NotTracked(path) => write!(f, "{} is not tracked", path),
+            Error::AlreadyExists(path) => write!(f, "{} already exists", path),
+            Error::FileAndFolder(path) => write!(f, "{} is both a file and a folder", path),
+            Error::CorruptIndex(path) => write!(f, "Index {} is corrupted", path),
+            Error::FileChanged(path) => {
+                write!(f, "File {} changed while it was being read", path)
+            }
+            Error::OldFormat(version) => write!(
+                f,
+                "The repository uses the old object format {}. Run pit migrate to upgrade it.",
+                version
+            ),
+            Error::UnsupportedFormat(version) => write!(
+                f,
+                "The repository format {} is not supported by this version of pit",
+                version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
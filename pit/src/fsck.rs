@@ -0,0 +1,337 @@
+use crate::error::{Error, Result};
+use crate::merge::MERGE_HEAD;
+use crate::object::{hash_content, Object, ObjectKind};
+use crate::repository::Repository;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+
+/// Something `Repository::fsck` found wrong with the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The object cannot be read, cannot be parsed or its content does not match its hash.
+    CorruptObject {
+        hash: String,
+        reason: String,
+    },
+    /// A pack or its index cannot be read.
+    CorruptPack(String),
+    /// An object, ref or the index refers to an object that does not exist.
+    MissingObject {
+        hash: String,
+        referenced_by: String,
+    },
+    /// An object is referenced as another kind than the one it has.
+    WrongKind {
+        hash: String,
+        expected: ObjectKind,
+        referenced_by: String,
+    },
+    BadRef {
+        name: String,
+        reason: String,
+    },
+    BadHead(String),
+    BadIndex(String),
+}
+
+impl fmt::Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckProblem::CorruptObject { hash, reason } => {
+                write!(f, "corrupt object {}: {}", hash, reason)
+            }
+            FsckProblem::CorruptPack(reason) => write!(f, "corrupt pack: {}", reason),
+            FsckProblem::MissingObject {
+                hash,
+                referenced_by,
+            } => write!(f, "missing object {} referenced by {}", hash, referenced_by),
+            FsckProblem::WrongKind {
+                hash,
+                expected,
+                referenced_by,
+            } => write!(
+                f,
+                "object {} referenced by {} is not a {}",
+                hash, referenced_by, expected
+            ),
+            FsckProblem::BadRef { name, reason } => write!(f, "bad ref {}: {}", name, reason),
+            FsckProblem::BadHead(reason) => write!(f, "bad HEAD: {}", reason),
+            FsckProblem::BadIndex(reason) => write!(f, "bad index: {}", reason),
+        }
+    }
+}
+
+/// What `Repository::fsck` checked and found.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Number of objects checked, loose and packed.
+    pub objects: usize,
+    pub problems: Vec<FsckProblem>,
+    /// Objects that nothing refers to: no object, branch, merge in progress or staged file.
+    /// They are harmless and removed by `pit gc`.
+    pub dangling: Vec<(String, ObjectKind)>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// An object that could be read: its kind and the objects it refers to.
+struct CheckedObject {
+    kind: ObjectKind,
+    references: Vec<(String, ObjectKind)>,
+}
+
+impl Repository {
+    /// Verifies the repository: every object must match its hash and parse, every object
+    /// referenced by a commit or a tree must exist with the right kind, and the refs, HEAD,
+    /// the merge in progress and the index must point to existing objects.
+    pub fn fsck(&self) -> Result<FsckReport> {
+        let mut report = FsckReport::default();
+        let mut objects: BTreeMap<String, CheckedObject> = BTreeMap::new();
+        let mut corrupt: HashSet<String> = HashSet::new();
+
+        for hash in self.objects().loose_objects()? {
+            report.objects += 1;
+            let content = self.objects().read_raw(hash.as_str());
+            check_object(&hash, content, &mut objects, &mut corrupt, &mut report);
+        }
+        match self.objects().packs() {
+            Ok(packs) => {
+                for pack in packs.iter() {
+                    for hash in pack.hashes() {
+                        report.objects += 1;
+                        let content = pack
+                            .read(hash)
+                            .and_then(|x| x.ok_or_else(|| Error::ObjectNotFound(hash.clone())));
+                        check_object(hash, content, &mut objects, &mut corrupt, &mut report);
+                    }
+                }
+            }
+            Err(err) => report
+                .problems
+                .push(FsckProblem::CorruptPack(err.to_string())),
+        }
+
+        let mut referenced: HashSet<String> = HashSet::new();
+        let mut check_reference =
+            |hash: &str, expected: ObjectKind, referenced_by: String, report: &mut FsckReport| {
+                referenced.insert(hash.to_string());
+                match objects.get(hash) {
+                    Some(object) if object.kind != expected => {
+                        report.problems.push(FsckProblem::WrongKind {
+                            hash: hash.to_string(),
+                            expected,
+                            referenced_by,
+                        })
+                    }
+                    Some(_) => {}
+                    // a corrupt object was already reported.
+                    None if corrupt.contains(hash) => {}
+                    None => report.problems.push(FsckProblem::MissingObject {
+                        hash: hash.to_string(),
+                        referenced_by,
+                    }),
+                }
+            };
+
+        for (hash, object) in &objects {
+            for (reference, kind) in &object.references {
+                let referenced_by = object.kind.to_string() + " " + hash.as_str();
+                check_reference(reference, *kind, referenced_by, &mut report);
+            }
+        }
+
+        let branches = self.branches()?;
+        for branch in &branches {
+            let name = branch.path();
+            let content = fs::read_to_string(self.pit_path().join(&name))?;
+            let commit = content.trim();
+            if commit.is_empty() {
+                continue;
+            }
+            if !is_hash(commit) {
+                report.problems.push(FsckProblem::BadRef {
+                    name,
+                    reason: "not a commit hash".to_string(),
+                });
+                continue;
+            }
+            check_reference(commit, ObjectKind::Commit, name, &mut report);
+        }
+
+        match fs::read_to_string(self.pit_path().join("HEAD")) {
+            Ok(head) => match head.trim().strip_prefix("refs/") {
+                Some(name) if !name.is_empty() && !name.contains('/') => {
+                    // a new repository has no ref file until its first commit.
+                    if !branches.is_empty() && !branches.iter().any(|x| x.name() == name) {
+                        report.problems.push(FsckProblem::BadHead(
+                            "branch ".to_string() + name + " does not exist",
+                        ));
+                    }
+                }
+                _ => report.problems.push(FsckProblem::BadHead(
+                    "does not name a branch: ".to_string() + head.trim(),
+                )),
+            },
+            Err(err) => report.problems.push(FsckProblem::BadHead(err.to_string())),
+        }
+
+        if let Ok(commit) = fs::read_to_string(self.pit_path().join(MERGE_HEAD)) {
+            check_reference(
+                commit.trim(),
+                ObjectKind::Commit,
+                MERGE_HEAD.to_string(),
+                &mut report,
+            );
+        }
+
+        match self.index() {
+            Ok(index) => {
+                for (path, entry) in index.staged() {
+                    let referenced_by = "staged file ".to_string() + path.as_str();
+                    check_reference(&entry.hash, ObjectKind::Blob, referenced_by, &mut report);
+                }
+            }
+            Err(err) => report.problems.push(FsckProblem::BadIndex(err.to_string())),
+        }
+
+        report.dangling = objects
+            .iter()
+            .filter(|(hash, _)| !referenced.contains(*hash))
+            .map(|(hash, object)| (hash.clone(), object.kind))
+            .collect();
+
+        Ok(report)
+    }
+}
+
+fn check_object(
+    hash: &str,
+    content: Result<Vec<u8>>,
+    objects: &mut BTreeMap<String, CheckedObject>,
+    corrupt: &mut HashSet<String>,
+    report: &mut FsckReport,
+) {
+    let mut fail = |reason: String| {
+        corrupt.insert(hash.to_string());
+        report.problems.push(FsckProblem::CorruptObject {
+            hash: hash.to_string(),
+            reason,
+        });
+    };
+    let content = match content {
+        Ok(content) => content,
+        Err(err) => return fail(err.to_string()),
+    };
+    if hash_content(&content) != hash {
+        return fail("content does not match the hash".to_string());
+    }
+    let object = match Object::parse(hash, &content) {
+        Ok(object) => object,
+        Err(err) => return fail(err.to_string()),
+    };
+    // parsing already refuses names such as `..`, a name used twice is checked here.
+    if let Object::Tree(tree) = &object {
+        let mut names: HashSet<&str> = HashSet::new();
+        if let Some(entry) = tree.entries.iter().find(|x| !names.insert(x.name.as_str())) {
+            return fail(format!("duplicate entry name {:?}", entry.name));
+        }
+    }
+
+    let references = match &object {
+        Object::Blob(_) => Vec::new(),
+        Object::Tree(tree) => tree
+            .entries
+            .iter()
+            // commit entries point into another repository.
+            .filter(|x| x.kind() != ObjectKind::Commit)
+            .map(|x| (x.hash.clone(), x.kind()))
+            .collect(),
+        Object::Commit(commit) => std::iter::once((commit.tree.clone(), ObjectKind::Tree))
+            .chain(
+                commit
+                    .parents
+                    .iter()
+                    .map(|x| (x.clone(), ObjectKind::Commit)),
+            )
+            .collect(),
+    };
+    objects.insert(
+        hash.to_string(),
+        CheckedObject {
+            kind: object.kind(),
+            references,
+        },
+    );
+}
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 40 && value.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{FileMode, Tree, TreeEntry};
+    use crate::testing::TemporaryRepository;
+
+    #[test]
+    fn reports_invalid_tree_entry_names() {
+        let repository = TemporaryRepository::new("fsck", "names");
+        let blob = repository.write_blob(b"content".to_vec()).unwrap();
+        let mut trees: Vec<String> = Vec::new();
+        for name in ["", ".", "..", "a/b"] {
+            let tree = Tree {
+                entries: vec![TreeEntry {
+                    mode: FileMode::Regular,
+                    hash: blob.clone(),
+                    name: name.to_string(),
+                }],
+            };
+            let object = Object::Tree(tree);
+            trees.push(repository.objects().write(&object).unwrap());
+        }
+
+        let report = repository.fsck().unwrap();
+
+        let corrupt: HashSet<&String> = report
+            .problems
+            .iter()
+            .filter_map(|x| match x {
+                FsckProblem::CorruptObject { hash, .. } => Some(hash),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(corrupt, trees.iter().collect());
+    }
+
+    #[test]
+    fn reports_duplicate_tree_entry_names() {
+        let repository = TemporaryRepository::new("fsck", "duplicates");
+        let blob = repository.write_blob(b"content".to_vec()).unwrap();
+        let folder = repository.write_tree(&BTreeMap::new()).unwrap();
+        let entry = |mode: FileMode, hash: &String| TreeEntry {
+            mode,
+            hash: hash.clone(),
+            name: "a".to_string(),
+        };
+        let tree = Tree {
+            entries: vec![
+                entry(FileMode::Regular, &blob),
+                entry(FileMode::Tree, &folder),
+            ],
+        };
+        let tree = repository.objects().write(&Object::Tree(tree)).unwrap();
+
+        let report = repository.fsck().unwrap();
+
+        assert!(report
+            .problems
+            .iter()
+            .any(|x| matches!(x, FsckProblem::CorruptObject { hash, .. } if *hash == tree)));
+    }
+}
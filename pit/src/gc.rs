@@ -0,0 +1,223 @@
+use crate::error::Result;
+use crate::merge::MERGE_HEAD;
+use crate::object::ObjectKind;
+use crate::pack::PackSource;
+use crate::repository::Repository;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// What `Repository::gc` removed, or would remove on a dry run.
+#[derive(Debug, Default)]
+pub struct GcStats {
+    pub reachable: usize,
+    /// Unreachable objects removed.
+    pub pruned: usize,
+    /// Unreachable objects kept because they are younger than the grace period.
+    pub recent: usize,
+    /// Bytes freed on disk.
+    pub reclaimed: u64,
+}
+
+impl Repository {
+    /// Returns every object reachable from a branch, the merge in progress or the index.
+    pub fn reachable_objects(&self) -> Result<HashSet<String>> {
+        let mut commits: Vec<String> = Vec::new();
+        for branch in self.branches()? {
+            commits.extend(self.read_ref(&branch)?);
+        }
+        if let Ok(commit) = fs::read_to_string(self.pit_path().join(MERGE_HEAD)) {
+            commits.push(commit.trim().to_string());
+        }
+
+        let mut reachable: HashSet<String> = HashSet::new();
+        let mut trees: Vec<String> = Vec::new();
+        while let Some(hash) = commits.pop() {
+            if !reachable.insert(hash.clone()) {
+                continue;
+            }
+            let commit = self.objects().read_commit(hash.as_str())?;
+            trees.push(commit.tree);
+            commits.extend(commit.parents);
+        }
+        while let Some(hash) = trees.pop() {
+            if !reachable.insert(hash.clone()) {
+                continue;
+            }
+            for entry in self.objects().read_tree(hash.as_str())?.entries {
+                match entry.kind() {
+                    ObjectKind::Tree => trees.push(entry.hash),
+                    _ => {
+                        reachable.insert(entry.hash);
+                    }
+                }
+            }
+        }
+        reachable.extend(self.staged_files()?.into_values().map(|x| x.hash));
+
+        Ok(reachable)
+    }
+
+    /// Deletes the objects nothing refers to once they are older than `grace`, so objects
+    /// written by a command still running are never removed. Packs holding unreachable
+    /// objects are rewritten without them. With `dry_run` nothing is deleted.
+    pub fn gc(&self, grace: Duration, dry_run: bool) -> Result<GcStats> {
+        let reachable = self.reachable_objects()?;
+        let mut stats = GcStats {
+            reachable: reachable.len(),
+            ..Default::default()
+        };
+
+        for hash in self.objects().loose_objects()? {
+            if reachable.contains(&hash) {
+                continue;
+            }
+            let object_path = self.objects().object_path(hash.as_str());
+            if !is_older_than(&object_path, grace)? {
+                stats.recent += 1;
+                continue;
+            }
+            stats.pruned += 1;
+            stats.reclaimed += fs::metadata(&object_path)?.len();
+            if !dry_run {
+                self.objects().remove_loose_object(hash.as_str())?;
+            }
+        }
+
+        for pack in self.objects().packs()?.iter() {
+            let unreachable = pack.hashes().filter(|x| !reachable.contains(*x)).count();
+            if unreachable == 0 {
+                continue;
+            }
+            if !is_older_than(pack.pack_path(), grace)? {
+                stats.recent += unreachable;
+                continue;
+            }
+
+            let objects: BTreeMap<String, PackSource> = pack
+                .hashes()
+                .filter(|x| reachable.contains(*x))
+                .map(|x| (x.clone(), PackSource::Pack(pack)))
+                .collect();
+            let old_size =
+                fs::metadata(pack.pack_path())?.len() + fs::metadata(pack.index_path())?.len();
+            let new_pack = if objects.is_empty() {
+                None
+            } else {
+                Some(self.objects().build_pack(&objects)?)
+            };
+            let new_size = match &new_pack {
+                Some(new_pack) => new_pack.size()?,
+                None => 0,
+            };
+            stats.pruned += unreachable;
+            stats.reclaimed += old_size.saturating_sub(new_size);
+            // the new pack is complete before the old one goes away.
+            match new_pack {
+                Some(new_pack) if dry_run => self.objects().discard_pack(new_pack)?,
+                Some(new_pack) => {
+                    self.objects().install_pack(new_pack)?;
+                }
+                None => {}
+            }
+            if dry_run {
+                continue;
+            }
+            self.objects().remove_pack(pack)?;
+        }
+
+        Ok(stats)
+    }
+}
+
+fn is_older_than(path: &Path, grace: Duration) -> Result<bool> {
+    let modified = fs::metadata(path)?.modified()?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+
+    Ok(age >= grace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::FileEntry;
+    use crate::testing::TemporaryRepository;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn keeps_the_objects_of_branches_merges_and_staged_files() {
+        let repository = TemporaryRepository::new("gc", "reachable");
+        repository.write_file("a", "a");
+        repository.commit_files(&["a"], "main");
+        repository.checkout("feature", true, false).unwrap();
+        repository.write_file("b", "b");
+        repository.commit_files(&["b"], "feature");
+        let merged = repository.write_blob(b"merged".to_vec()).unwrap();
+        let files = BTreeMap::from([("./m".to_string(), FileEntry::regular(&merged))]);
+        let tree = repository.write_tree(&files).unwrap();
+        let merge_head = repository
+            .write_commit(&tree, Vec::new(), "merged")
+            .unwrap();
+        fs::write(repository.pit_path().join(MERGE_HEAD), &merge_head).unwrap();
+        repository.write_file("c", "c");
+        repository.add(&["c".to_string()]).unwrap();
+        let reachable = repository.reachable_objects().unwrap();
+        let garbage = repository.write_blob(b"garbage".to_vec()).unwrap();
+
+        let stats = repository.gc(Duration::ZERO, false).unwrap();
+
+        assert_eq!((stats.reachable, stats.pruned), (reachable.len(), 1));
+        assert!(!repository.objects().contains(&garbage));
+        assert!(reachable.contains(&merged));
+        for hash in &reachable {
+            assert!(repository.objects().contains(hash), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn keeps_recent_objects_and_deletes_nothing_on_a_dry_run() {
+        let repository = TemporaryRepository::new("gc", "grace");
+        let garbage = repository.write_blob(b"garbage".to_vec()).unwrap();
+
+        let recent = repository.gc(HOUR, false).unwrap();
+        let dry_run = repository.gc(Duration::ZERO, true).unwrap();
+
+        assert_eq!((recent.recent, recent.pruned), (1, 0));
+        assert_eq!(dry_run.pruned, 1);
+        assert!(dry_run.reclaimed > 0);
+        assert!(repository.objects().contains(&garbage));
+    }
+
+    #[test]
+    fn rewrites_packs_without_their_unreachable_objects() {
+        let repository = TemporaryRepository::new("gc", "pack");
+        repository.write_file("a", "a");
+        repository.commit_files(&["a"], "main");
+        let garbage = repository.write_blob(b"garbage".to_vec()).unwrap();
+        repository.objects().repack().unwrap();
+        let pack = repository.objects().packs().unwrap()[0]
+            .pack_path()
+            .to_path_buf();
+
+        let dry_run = repository.gc(Duration::ZERO, true).unwrap();
+        assert_eq!(dry_run.pruned, 1);
+        assert!(pack.exists());
+        assert_eq!(fs::read_dir(pack.parent().unwrap()).unwrap().count(), 2);
+        let stats = repository.gc(Duration::ZERO, false).unwrap();
+
+        assert_eq!(stats.pruned, 1);
+        assert!(!pack.exists());
+        assert_eq!(repository.objects().packs().unwrap().len(), 1);
+        assert!(!repository.objects().contains(&garbage));
+        let head = repository.head_commit().unwrap();
+        let files = repository.read_commit_files(head.as_deref()).unwrap();
+        assert_eq!(
+            repository.read_blob_content(&files["./a"].hash).unwrap(),
+            b"a"
+        );
+    }
+}
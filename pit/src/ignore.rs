@@ -0,0 +1,40 @@
+use crate::error::Result;
+use crate::repository::{normalize_path, PIT_FOLDER};
+use std::fs;
+use std::path::Path;
+
+pub const IGNORE_FILE: &str = ".pitignore";
+
+/// Reads every `.pitignore` under `root`. Each line names a file or folder relative to the
+/// folder of the `.pitignore`; the result holds normalized paths (`./folder/file`).
+pub fn read_ignored_files(root: &Path) -> Result<Vec<String>> {
+    let mut ignored: Vec<String> = Vec::new();
+    read_ignored_folder(root, ".", &mut ignored)?;
+
+    Ok(ignored)
+}
+
+fn read_ignored_folder(root: &Path, path: &str, ignored: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(root.join(path))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let entry_path = path.to_string() + "/" + name.as_str();
+        if name == PIT_FOLDER {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            read_ignored_folder(root, entry_path.as_str(), ignored)?;
+        } else if name == IGNORE_FILE {
+            for line in fs::read_to_string(entry.path())?.lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                ignored.push(normalize_path(
+                    (path.to_string() + "/" + line.trim()).as_str(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
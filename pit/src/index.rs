@@ -0,0 +1,479 @@
+use crate::error::{Error, Result};
+use crate::object::{decode_hash, encode_hash, hash_content, FileMode};
+use crate::pack::{read_u32, read_u64};
+use crate::repository::{write_file, FileEntry};
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const INDEX_FILE: &str = "index";
+
+const INDEX_SIGNATURE: &[u8; 4] = b"INDX";
+const INDEX_VERSION: u32 = 2;
+/// Flag of the entries added since the last commit.
+const STAGED: u32 = 1;
+/// Flag of the committed files the next commit removes.
+const REMOVED: u32 = 2;
+
+/// A file of the index: its blob and what the file looked like on disk when that blob was
+/// read from it. Entries are staged when the file was added since the last commit, the
+/// others only remember the stat data of committed files so status does not hash them again.
+/// A removed entry records that the next commit drops the committed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub hash: String,
+    pub size: u64,
+    /// Modification time, in seconds and nanoseconds since the Unix epoch.
+    pub mtime_seconds: u64,
+    pub mtime_nanoseconds: u32,
+    /// Inode number on unix, 0 elsewhere.
+    pub inode: u64,
+    pub mode: FileMode,
+    pub staged: bool,
+    pub removed: bool,
+}
+
+impl IndexEntry {
+    pub fn new(hash: &str, metadata: &Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        IndexEntry {
+            hash: hash.to_string(),
+            size: metadata.len(),
+            mtime_seconds: mtime.as_secs(),
+            mtime_nanoseconds: mtime.subsec_nanos(),
+            inode: inode(metadata),
+            mode: file_mode(metadata),
+            staged: true,
+            removed: false,
+        }
+    }
+
+    /// An entry for a file that is not on disk yet. Its stat data never matches a file.
+    pub fn without_stat(file: &FileEntry) -> Self {
+        IndexEntry {
+            hash: file.hash.clone(),
+            size: 0,
+            mtime_seconds: 0,
+            mtime_nanoseconds: 0,
+            inode: 0,
+            mode: file.mode,
+            staged: true,
+            removed: false,
+        }
+    }
+
+    /// An entry staging the removal of the committed `file`.
+    pub fn removal(file: &FileEntry) -> Self {
+        IndexEntry {
+            removed: true,
+            ..IndexEntry::without_stat(file)
+        }
+    }
+
+    /// The blob and the mode of the entry.
+    pub fn file(&self) -> FileEntry {
+        FileEntry::new(self.hash.as_str(), self.mode)
+    }
+
+    /// Modification time recorded in the entry.
+    fn modified(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(self.mtime_seconds, self.mtime_nanoseconds)
+    }
+
+    /// Tells whether the file still has the stat data recorded in the entry.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let other = IndexEntry::new(self.hash.as_str(), metadata);
+        self.size == other.size
+            && self.mtime_seconds == other.mtime_seconds
+            && self.mtime_nanoseconds == other.mtime_nanoseconds
+            && self.inode == other.inode
+            && self.mode == other.mode
+    }
+}
+
+/// The `.pit/index` file: the files added or removed since the last commit and the stat
+/// data of the committed files, sorted by path.
+///
+/// The file starts with `INDX`, the version and the number of entries. Each entry is the
+/// 20 byte blob hash, the size, the modification time in seconds and nanoseconds, the inode,
+/// the mode, the flags, the length of the path and the path. Version 1 had no inode and no
+/// flags and only held staged files. The file ends with the SHA-1 of everything before it,
+/// so a damaged index is detected instead of silently dropping staged files.
+#[derive(Debug, Clone)]
+pub struct Index {
+    path: PathBuf,
+    entries: BTreeMap<String, IndexEntry>,
+    /// When the index was last written. Files modified at that time or later may have
+    /// changed again without their stat data changing.
+    modified: Option<SystemTime>,
+    changed: bool,
+}
+
+impl Index {
+    pub fn new(path: PathBuf) -> Self {
+        Index {
+            path,
+            entries: BTreeMap::new(),
+            modified: None,
+            changed: false,
+        }
+    }
+
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Index::new(path)),
+            Err(err) => return Err(err.into()),
+        };
+        let entries = parse_entries(&content)
+            .ok_or_else(|| Error::CorruptIndex(path.display().to_string()))?;
+        let modified = fs::metadata(&path)?.modified().ok();
+
+        Ok(Index {
+            path,
+            entries,
+            modified,
+            changed: false,
+        })
+    }
+
+    /// Every entry, staged or not, as path -> entry.
+    pub fn entries(&self) -> &BTreeMap<String, IndexEntry> {
+        &self.entries
+    }
+
+    /// Files added since the last commit.
+    pub fn staged(&self) -> impl Iterator<Item = (&String, &IndexEntry)> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.staged && !entry.removed)
+    }
+
+    /// Committed files the next commit removes.
+    pub fn removed(&self) -> impl Iterator<Item = &String> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.removed)
+            .map(|(path, _)| path)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries.get(path)
+    }
+
+    pub fn insert(&mut self, path: &str, entry: IndexEntry) {
+        if self.entries.get(path) != Some(&entry) {
+            self.entries.insert(path.to_string(), entry);
+            self.changed = true;
+        }
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.changed |= self.entries.remove(path).is_some();
+    }
+
+    /// Keeps the entries as stat data of the committed files, after a commit or when the
+    /// staged files are dropped. Staged removals are forgotten.
+    pub fn unstage_all(&mut self) {
+        let count = self.entries.len();
+        self.entries.retain(|_, entry| !entry.removed);
+        self.changed |= self.entries.len() != count;
+        for entry in self.entries.values_mut() {
+            self.changed |= entry.staged;
+            entry.staged = false;
+        }
+    }
+
+    /// Tells whether entries were inserted, removed or unstaged since the index was loaded.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Tells whether the stat data of the entry can be trusted for a file with `metadata`:
+    /// the entry is for the blob `hash`, the file still has the recorded stat data, and it
+    /// was not modified in the same instant the index was written, when a later change
+    /// could keep the same modification time.
+    pub fn is_up_to_date(&self, path: &str, hash: &str, metadata: &Metadata) -> bool {
+        let Some(entry) = self.entries.get(path) else {
+            return false;
+        };
+        let (Some(index_modified), Ok(file_modified)) = (self.modified, metadata.modified()) else {
+            return false;
+        };
+
+        entry.hash == hash && entry.matches(metadata) && file_modified < index_modified
+    }
+
+    /// Writes the index through a temporary file so readers never see half of it.
+    ///
+    /// A file modified in the same instant the index is written could change again without
+    /// its stat data changing, and a later write would make the index look newer than that
+    /// change. Like git, such entries are written without their stat data, so the file is
+    /// hashed again next time.
+    pub fn save(&self) -> Result<()> {
+        write_file(&self.path, &self.serialize(None)?)?;
+        let written = fs::metadata(&self.path)?.modified()?;
+        let is_racy = |x: &IndexEntry| x.mtime_seconds != 0 && x.modified() >= written;
+        if self.entries.values().any(is_racy) {
+            write_file(&self.path, &self.serialize(Some(written))?)?;
+        }
+
+        Ok(())
+    }
+
+    /// The content of the index file, without the stat data of the entries modified at
+    /// `written` or later.
+    fn serialize(&self, written: Option<SystemTime>) -> Result<Vec<u8>> {
+        let mut content: Vec<u8> = INDEX_SIGNATURE.to_vec();
+        content.extend(INDEX_VERSION.to_be_bytes());
+        content.extend((self.entries.len() as u32).to_be_bytes());
+        for (path, entry) in &self.entries {
+            let smudged;
+            let entry = match written {
+                Some(written) if entry.mtime_seconds != 0 && entry.modified() >= written => {
+                    smudged = IndexEntry {
+                        staged: entry.staged,
+                        removed: entry.removed,
+                        ..IndexEntry::without_stat(&entry.file())
+                    };
+                    &smudged
+                }
+                _ => entry,
+            };
+            let hash = decode_hash(entry.hash.as_str());
+            if hash.len() != 20 {
+                return Err(Error::CorruptObject(entry.hash.clone()));
+            }
+            content.extend(hash);
+            content.extend(entry.size.to_be_bytes());
+            content.extend(entry.mtime_seconds.to_be_bytes());
+            content.extend(entry.mtime_nanoseconds.to_be_bytes());
+            content.extend(entry.inode.to_be_bytes());
+            content.extend(entry.mode.bits().to_be_bytes());
+            let flags = match (entry.staged, entry.removed) {
+                (_, true) => STAGED | REMOVED,
+                (true, false) => STAGED,
+                (false, false) => 0,
+            };
+            content.extend(flags.to_be_bytes());
+            content.extend((path.len() as u32).to_be_bytes());
+            content.extend(path.as_bytes());
+        }
+        content.extend(decode_hash(hash_content(&content).as_str()));
+
+        Ok(content)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn parse_entries(content: &[u8]) -> Option<BTreeMap<String, IndexEntry>> {
+    let (content, checksum) = content.split_at(content.len().checked_sub(20)?);
+    if encode_hash(checksum) != hash_content(content)
+        || content.get(..4) != Some(INDEX_SIGNATURE.as_slice())
+    {
+        return None;
+    }
+    let version = read_u32(content, 4)?;
+    if version != 1 && version != INDEX_VERSION {
+        return None;
+    }
+    let count = read_u32(content, 8)?;
+    let mut entries: BTreeMap<String, IndexEntry> = BTreeMap::new();
+    let mut position: usize = 12;
+    let mut take = |length: usize| {
+        let bytes = content.get(position..position.checked_add(length)?)?;
+        position += length;
+        Some(bytes)
+    };
+    for _ in 0..count {
+        let hash = encode_hash(take(20)?);
+        let size = read_u64(take(8)?, 0)?;
+        let mtime_seconds = read_u64(take(8)?, 0)?;
+        let mtime_nanoseconds = read_u32(take(4)?, 0)?;
+        let inode = match version {
+            1 => 0,
+            _ => read_u64(take(8)?, 0)?,
+        };
+        let mode = FileMode::from_bits(read_u32(take(4)?, 0)?)?;
+        let flags = match version {
+            1 => STAGED,
+            _ => read_u32(take(4)?, 0)?,
+        };
+        let path_length = read_u32(take(4)?, 0)? as usize;
+        let path = take(path_length)?;
+        entries.insert(
+            String::from_utf8(path.to_vec()).ok()?,
+            IndexEntry {
+                hash,
+                size,
+                mtime_seconds,
+                mtime_nanoseconds,
+                inode,
+                mode,
+                staged: flags & STAGED != 0,
+                removed: flags & REMOVED != 0,
+            },
+        );
+    }
+    if position != content.len() {
+        return None;
+    }
+
+    Some(entries)
+}
+
+/// The mode of a file from its `symlink_metadata`: symbolic links get the link mode and
+/// executable files on unix the executable mode, everything else is a regular file.
+pub fn file_mode(metadata: &Metadata) -> FileMode {
+    if metadata.file_type().is_symlink() {
+        return FileMode::Symlink;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return FileMode::Executable;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    FileMode::Regular
+}
+
+fn inode(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TemporaryFolder;
+
+    const HASH: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+
+    fn index_path(folder: &TemporaryFolder) -> PathBuf {
+        folder.path().join(INDEX_FILE)
+    }
+
+    fn entry(mode: FileMode, staged: bool, removed: bool) -> IndexEntry {
+        IndexEntry {
+            hash: HASH.to_string(),
+            size: 6,
+            mtime_seconds: 1_700_000_000,
+            mtime_nanoseconds: 123_456_789,
+            inode: 42,
+            mode,
+            staged,
+            removed,
+        }
+    }
+
+    #[test]
+    fn reads_back_what_was_saved() {
+        let folder = TemporaryFolder::new("index", "round-trip");
+        let path = index_path(&folder);
+        let mut index = Index::new(path.clone());
+        index.insert("./a b.txt", entry(FileMode::Regular, true, false));
+        index.insert("./bin/run", entry(FileMode::Executable, false, false));
+        index.insert("./naïve", entry(FileMode::Symlink, true, true));
+        index.save().unwrap();
+
+        let loaded = Index::load(path.clone()).unwrap();
+
+        assert_eq!(loaded.entries(), index.entries());
+        let staged: Vec<&String> = loaded.staged().map(|(path, _)| path).collect();
+        assert_eq!(staged, ["./a b.txt"]);
+        assert_eq!(loaded.removed().collect::<Vec<_>>(), ["./naïve"]);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let mut content: Vec<u8> = INDEX_SIGNATURE.to_vec();
+        content.extend(1u32.to_be_bytes());
+        content.extend(1u32.to_be_bytes());
+        content.extend(decode_hash(HASH));
+        content.extend(6u64.to_be_bytes());
+        content.extend(1_700_000_000u64.to_be_bytes());
+        content.extend(123_456_789u32.to_be_bytes());
+        content.extend(FileMode::Executable.bits().to_be_bytes());
+        content.extend(7u32.to_be_bytes());
+        content.extend(b"./a.txt");
+        content.extend(decode_hash(hash_content(&content).as_str()));
+
+        let entries = parse_entries(&content).unwrap();
+
+        let expected = IndexEntry {
+            inode: 0,
+            ..entry(FileMode::Executable, true, false)
+        };
+        assert_eq!(entries.get("./a.txt"), Some(&expected));
+    }
+
+    #[test]
+    fn rejects_a_damaged_index() {
+        let folder = TemporaryFolder::new("index", "damaged");
+        let path = index_path(&folder);
+        let mut index = Index::new(path.clone());
+        index.insert("./a.txt", entry(FileMode::Regular, true, false));
+        index.save().unwrap();
+        let mut content = fs::read(&path).unwrap();
+
+        assert!(parse_entries(&content).is_some());
+        content[20] ^= 1;
+        assert!(parse_entries(&content).is_none());
+        assert!(parse_entries(&content[..content.len() - 1]).is_none());
+        assert!(parse_entries(b"").is_none());
+    }
+
+    #[test]
+    fn drops_the_stat_data_of_files_modified_while_saving() {
+        let folder = TemporaryFolder::new("index", "racy");
+        let hour = Duration::from_secs(60 * 60);
+        let mut index = Index::new(index_path(&folder));
+        for (name, modified) in [
+            ("old", SystemTime::now() - hour),
+            ("racy", SystemTime::now() + hour),
+        ] {
+            let path = folder.path().join(name);
+            let file = fs::File::create(&path).unwrap();
+            file.set_modified(modified).unwrap();
+            let entry = IndexEntry::new(HASH, &fs::metadata(&path).unwrap());
+            index.insert(
+                name,
+                IndexEntry {
+                    staged: false,
+                    ..entry
+                },
+            );
+        }
+        index.save().unwrap();
+
+        let loaded = Index::load(index_path(&folder)).unwrap();
+
+        let metadata = |name: &str| fs::metadata(folder.path().join(name)).unwrap();
+        assert!(loaded.is_up_to_date("old", HASH, &metadata("old")));
+        assert!(!loaded.get("racy").unwrap().matches(&metadata("racy")));
+        assert_eq!(loaded.get("racy").unwrap().hash, HASH);
+        assert!(!loaded.get("racy").unwrap().staged);
+    }
+}
@@ -0,0 +1,41 @@
+//! Pit's object model and repository operations. The `pit` command line tool is a thin
+//! wrapper over this crate.
+
+pub mod delta;
+pub mod error;
+pub mod fsck;
+pub mod gc;
+pub mod ignore;
+pub mod index;
+pub mod log;
+pub mod merge;
+pub mod migrate;
+pub mod object;
+pub mod object_store;
+pub mod pack;
+pub mod refs;
+pub mod rename;
+pub mod repository;
+pub mod staging;
+pub mod status;
+pub mod worktree;
+
+#[cfg(test)]
+mod testing;
+
+pub use error::{Error, Result};
+pub use fsck::{FsckProblem, FsckReport};
+pub use gc::GcStats;
+pub use index::{Index, IndexEntry};
+pub use log::LogEntry;
+pub use merge::{MergeChunk, MergeOutcome};
+pub use migrate::Migration;
+pub use object::{Blob, Commit, FileMode, Object, ObjectKind, Tree, TreeEntry};
+pub use object_store::ObjectStore;
+pub use pack::{PackIndex, RepackStats};
+pub use refs::Ref;
+pub use rename::{FileChanges, Rename};
+pub use repository::{FileEntry, Repository};
+pub use staging::{AddResult, RemoveResult};
+pub use status::Status;
+pub use worktree::{RestoreResult, TreeChanges};
@@ -0,0 +1,62 @@
+use crate::error::Result;
+use crate::object::Commit;
+use crate::rename::FileChanges;
+use crate::repository::Repository;
+use std::collections::{HashMap, VecDeque};
+
+/// A commit of the history and its hash.
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub hash: String,
+    pub commit: Commit,
+}
+
+impl Repository {
+    /// Lists the commits reachable from `commit`. Every commit comes before its parents,
+    /// and the history of the first parent is followed before the merged branches.
+    pub fn log(&self, commit: &str) -> Result<Vec<LogEntry>> {
+        let mut commits: HashMap<String, Commit> = HashMap::new();
+        // number of reachable children of each commit that are not listed yet.
+        let mut children: HashMap<String, usize> = HashMap::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(commit.to_string());
+        while let Some(hash) = queue.pop_front() {
+            if commits.contains_key(&hash) {
+                continue;
+            }
+            let commit = self.objects().read_commit(hash.as_str())?;
+            for parent in &commit.parents {
+                *children.entry(parent.clone()).or_default() += 1;
+                queue.push_back(parent.clone());
+            }
+            commits.insert(hash, commit);
+        }
+
+        let mut entries: Vec<LogEntry> = Vec::new();
+        let mut stack: Vec<String> = vec![commit.to_string()];
+        while let Some(hash) = stack.pop() {
+            let Some(commit) = commits.remove(&hash) else {
+                continue;
+            };
+            for parent in commit.parents.iter().rev() {
+                let count = children.entry(parent.clone()).or_default();
+                *count -= 1;
+                if *count == 0 {
+                    stack.push(parent.clone());
+                }
+            }
+            entries.push(LogEntry { hash, commit });
+        }
+
+        Ok(entries)
+    }
+
+    /// Compares the files of `commit` with the files of its first parent.
+    pub fn commit_changes(&self, commit: &Commit, threshold: u8) -> Result<FileChanges> {
+        let parent = commit.parents.first().map(|x| x.as_str());
+        let old_files = self.read_commit_files(parent)?;
+        let new_files = self.read_tree_files(commit.tree.as_str())?;
+
+        self.compare_files(&old_files, &new_files, threshold)
+    }
+}
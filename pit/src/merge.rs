@@ -0,0 +1,702 @@
+use crate::error::{Error, Result};
+use crate::index::file_mode;
+use crate::object::{read_work_file, FileMode};
+use crate::refs::Ref;
+use crate::repository::{FileEntry, Repository};
+use crate::worktree::TreeChanges;
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
+pub const MERGE_MSG: &str = "MERGE_MSG";
+pub const MERGE_CONFLICTS: &str = "MERGE_CONFLICTS";
+
+/// Result of `Repository::merge`.
+#[derive(Debug)]
+pub enum MergeOutcome {
+    /// The branch has nothing the current branch does not already have.
+    UpToDate,
+    /// The current branch only moved forward to the merged commit.
+    FastForward {
+        commit: String,
+        changes: TreeChanges,
+    },
+    /// A merge commit with two parents was created.
+    Merged {
+        commit: String,
+        changes: TreeChanges,
+    },
+    /// Conflicting files were written with markers and the merge waits for `continue_merge`.
+    Conflicts {
+        paths: Vec<String>,
+        changes: TreeChanges,
+    },
+}
+
+/// A change of one side, replacing the base lines `start..end` with `lines`.
+#[derive(Debug, Clone)]
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+    ours: bool,
+}
+
+#[derive(Debug)]
+pub enum MergeChunk<'a> {
+    Resolved(Vec<&'a str>),
+    Conflict {
+        ours: Vec<&'a str>,
+        theirs: Vec<&'a str>,
+    },
+}
+
+#[derive(Debug)]
+struct MergeConflict {
+    path: String,
+    // file content with conflict markers, None when one side deleted the file or it is binary.
+    content: Option<String>,
+}
+
+#[derive(Debug)]
+struct MergeResult {
+    // conflicting paths keep the file of the side that still has it.
+    files: BTreeMap<String, FileEntry>,
+    conflicts: Vec<MergeConflict>,
+}
+
+/// Commits the merge is done between.
+struct MergeCommits {
+    head: Ref,
+    head_commit: String,
+    branch_commit: String,
+}
+
+impl Repository {
+    pub fn is_merging(&self) -> bool {
+        self.pit_path().join(MERGE_HEAD).is_file()
+    }
+
+    /// Returns the paths that were left with conflicts by an unfinished merge.
+    pub fn merge_conflicts(&self) -> Result<Vec<String>> {
+        if !self.is_merging() {
+            return Ok(Vec::new());
+        }
+        let content = match fs::read_to_string(self.pit_path().join(MERGE_CONFLICTS)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => Default::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(content
+            .lines()
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect())
+    }
+
+    /// Finds the best common ancestor of both commits: a commit reachable from both that is
+    /// not an ancestor of another such commit. When there are several, as after criss-cross
+    /// merges, the one closest to `branch_commit` is returned.
+    pub fn merge_base(&self, head_commit: &str, branch_commit: &str) -> Result<Option<String>> {
+        let head_ancestors = self.ancestors(head_commit)?;
+
+        // the common ancestors reachable from the branch without going through another one.
+        let mut candidates: Vec<String> = Vec::new();
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(branch_commit.to_string());
+        while let Some(commit) = queue.pop_front() {
+            if !visited.insert(commit.clone()) {
+                continue;
+            }
+            if head_ancestors.contains(&commit) {
+                candidates.push(commit);
+                continue;
+            }
+            queue.extend(self.objects().read_commit(commit.as_str())?.parents);
+        }
+
+        // a candidate reachable from another one is further away from both commits.
+        let mut redundant: HashSet<String> = HashSet::new();
+        for candidate in &candidates {
+            if redundant.contains(candidate) || candidates.len() == 1 {
+                continue;
+            }
+            let ancestors = self.ancestors(candidate.as_str())?;
+            redundant.extend(
+                candidates
+                    .iter()
+                    .filter(|x| *x != candidate && ancestors.contains(*x))
+                    .cloned(),
+            );
+        }
+
+        Ok(candidates.into_iter().find(|x| !redundant.contains(x)))
+    }
+
+    /// Returns `commit` and every commit reachable from it.
+    fn ancestors(&self, commit: &str) -> Result<HashSet<String>> {
+        let mut ancestors: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(commit.to_string());
+        while let Some(commit) = queue.pop_front() {
+            if !ancestors.insert(commit.clone()) {
+                continue;
+            }
+            queue.extend(self.objects().read_commit(commit.as_str())?.parents);
+        }
+
+        Ok(ancestors)
+    }
+
+    /// Merges `branch` into the current branch. Without `message` the merge commit is called
+    /// "Merge branch '<branch>'". Uncommitted changes, and untracked files the merge would
+    /// replace, are only overwritten with `force`.
+    pub fn merge(&self, branch: &str, message: Option<&str>, force: bool) -> Result<MergeOutcome> {
+        if self.is_merging() {
+            return Err(Error::MergeInProgress);
+        }
+        let branch_ref = Ref::branch(branch);
+        if !self.ref_exists(&branch_ref) {
+            return Err(Error::BranchNotFound(branch.to_string()));
+        }
+        let head = self.head()?;
+        let head_commit = self.read_ref(&head)?.ok_or(Error::NoCommits)?;
+        let branch_commit = self.read_ref(&branch_ref)?.ok_or(Error::NoCommits)?;
+        if branch_commit == head_commit {
+            return Ok(MergeOutcome::UpToDate);
+        }
+        let commits = MergeCommits {
+            head,
+            head_commit,
+            branch_commit,
+        };
+
+        let merge_base = self.merge_base(&commits.head_commit, &commits.branch_commit)?;
+        if merge_base.as_deref() == Some(commits.branch_commit.as_str()) {
+            return Ok(MergeOutcome::UpToDate);
+        }
+        let head_files = self.read_commit_files(Some(commits.head_commit.as_str()))?;
+        if merge_base.as_deref() == Some(commits.head_commit.as_str()) {
+            let branch_files = self.read_commit_files(Some(commits.branch_commit.as_str()))?;
+            self.ensure_clean_working_tree(&branch_files, force)?;
+            let changes = self.write_working_tree(&head_files, &branch_files)?;
+            self.write_ref(&commits.head, commits.branch_commit.as_str())?;
+            self.clear_staging()?;
+
+            return Ok(MergeOutcome::FastForward {
+                commit: commits.branch_commit,
+                changes,
+            });
+        }
+
+        let default_message = "Merge branch '".to_string() + branch + "'";
+        let message = message.unwrap_or(default_message.as_str());
+        let result = self.merge_commits(merge_base.as_deref(), &commits, branch)?;
+        self.ensure_clean_working_tree(&result.files, force)?;
+        let changes = self.write_working_tree(&head_files, &result.files)?;
+
+        if !result.conflicts.is_empty() {
+            let mut paths: Vec<String> = Vec::new();
+            for conflict in &result.conflicts {
+                if let Some(content) = &conflict.content {
+                    fs::write(self.work_path(conflict.path.as_str()), content)?;
+                }
+                paths.push(conflict.path.clone());
+            }
+            self.stage_merged_files(&head_files, &result)?;
+            fs::write(self.pit_path().join(MERGE_HEAD), &commits.branch_commit)?;
+            fs::write(self.pit_path().join(MERGE_MSG), message)?;
+            fs::write(self.pit_path().join(MERGE_CONFLICTS), paths.join("\n"))?;
+
+            return Ok(MergeOutcome::Conflicts { paths, changes });
+        }
+
+        let commit = self.create_merge_commit(&commits, &result.files, message)?;
+
+        Ok(MergeOutcome::Merged { commit, changes })
+    }
+
+    /// Creates the merge commit once the conflict markers are gone from the conflicting files.
+    pub fn continue_merge(&self) -> Result<String> {
+        let commits = self.read_merge_state()?;
+        let merge_base = self.merge_base(&commits.head_commit, &commits.branch_commit)?;
+        let mut result = self.merge_commits(merge_base.as_deref(), &commits, "")?;
+
+        let mut unresolved: Vec<String> = Vec::new();
+        for path in self.merge_conflicts()? {
+            let file_path = self.work_path(path.as_str());
+            // links are read as their target, even when it does not exist.
+            let metadata = match fs::symlink_metadata(&file_path) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    // the conflict was resolved by deleting the file.
+                    result.files.remove(&path);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let is_link = metadata.file_type().is_symlink();
+            let content = read_work_file(&file_path)?;
+            let has_markers = !is_link
+                && std::str::from_utf8(&content).is_ok_and(|x| {
+                    x.lines()
+                        .any(|x| x.starts_with("<<<<<<<") || x.starts_with(">>>>>>>"))
+                });
+            if has_markers {
+                unresolved.push(path);
+                continue;
+            }
+            let hash = self.write_blob(content)?;
+            // a link may have been replaced by a file while resolving, or the reverse.
+            let mode = match result.files.get(&path).map(|x| x.mode) {
+                _ if is_link => FileMode::Symlink,
+                Some(FileMode::Symlink) | None => file_mode(&metadata),
+                Some(mode) => mode,
+            };
+            result.files.insert(path, FileEntry::new(&hash, mode));
+        }
+        if !unresolved.is_empty() {
+            return Err(Error::UnresolvedConflicts(unresolved));
+        }
+
+        let message =
+            fs::read_to_string(self.pit_path().join(MERGE_MSG)).unwrap_or("Merge".to_string());
+        let commit = self.create_merge_commit(&commits, &result.files, message.as_str())?;
+        self.remove_merge_state()?;
+
+        Ok(commit)
+    }
+
+    /// Stops the merge in progress and restores the files of the current branch.
+    pub fn abort_merge(&self) -> Result<TreeChanges> {
+        let commits = self.read_merge_state()?;
+        let merge_base = self.merge_base(&commits.head_commit, &commits.branch_commit)?;
+        let mut merged_files = self
+            .merge_commits(merge_base.as_deref(), &commits, "")?
+            .files;
+        let head_files = self.read_commit_files(Some(commits.head_commit.as_str()))?;
+        for (path, file) in &head_files {
+            merged_files.entry(path.clone()).or_insert(file.clone());
+        }
+
+        let changes = self.update_working_tree(&merged_files, &head_files)?;
+        self.clear_staging()?;
+        self.remove_merge_state()?;
+
+        Ok(changes)
+    }
+
+    fn read_merge_state(&self) -> Result<MergeCommits> {
+        if !self.is_merging() {
+            return Err(Error::NoMergeInProgress);
+        }
+        let head = self.head()?;
+        let head_commit = self.read_ref(&head)?.ok_or(Error::NoCommits)?;
+        let branch_commit = fs::read_to_string(self.pit_path().join(MERGE_HEAD))?;
+
+        Ok(MergeCommits {
+            head,
+            head_commit,
+            branch_commit: branch_commit.trim().to_string(),
+        })
+    }
+
+    fn remove_merge_state(&self) -> Result<()> {
+        for file in [MERGE_HEAD, MERGE_MSG, MERGE_CONFLICTS] {
+            match fs::remove_file(self.pit_path().join(file)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge_commits(
+        &self,
+        merge_base: Option<&str>,
+        commits: &MergeCommits,
+        branch: &str,
+    ) -> Result<MergeResult> {
+        let base_files = self.read_commit_files(merge_base)?;
+        let head_files = self.read_commit_files(Some(commits.head_commit.as_str()))?;
+        let branch_files = self.read_commit_files(Some(commits.branch_commit.as_str()))?;
+
+        self.merge_trees(&base_files, &head_files, &branch_files, branch)
+    }
+
+    /// Adds the files the merge changed to the index so they show as tracked changes.
+    fn stage_merged_files(
+        &self,
+        head_files: &BTreeMap<String, FileEntry>,
+        result: &MergeResult,
+    ) -> Result<()> {
+        let conflicts: Vec<&String> = result.conflicts.iter().map(|x| &x.path).collect();
+        let mut index = self.index()?;
+        index.unstage_all();
+        for (path, file) in &result.files {
+            if head_files.get(path) != Some(file) && !conflicts.contains(&path) {
+                index.insert(path, self.index_entry(path, file)?);
+            }
+        }
+
+        index.save()
+    }
+
+    fn create_merge_commit(
+        &self,
+        commits: &MergeCommits,
+        files: &BTreeMap<String, FileEntry>,
+        message: &str,
+    ) -> Result<String> {
+        let tree = self.write_tree(files)?;
+        let parents = vec![commits.head_commit.clone(), commits.branch_commit.clone()];
+        let commit = self.write_commit(tree.as_str(), parents, message)?;
+        self.write_ref(&commits.head, commit.as_str())?;
+        self.clear_staging()?;
+
+        Ok(commit)
+    }
+
+    /// Merges the files of both sides against their common base. Files changed differently
+    /// on both sides are returned as conflicts.
+    fn merge_trees(
+        &self,
+        base: &BTreeMap<String, FileEntry>,
+        ours: &BTreeMap<String, FileEntry>,
+        theirs: &BTreeMap<String, FileEntry>,
+        branch: &str,
+    ) -> Result<MergeResult> {
+        let mut merged: BTreeMap<String, FileEntry> = BTreeMap::new();
+        let mut conflicts: Vec<MergeConflict> = Vec::new();
+        let paths: BTreeSet<&String> = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .collect();
+
+        for path in paths {
+            let base_file = base.get(path);
+            let our_file = ours.get(path);
+            let their_file = theirs.get(path);
+
+            let result = if our_file == their_file || their_file == base_file {
+                our_file.cloned()
+            } else if our_file == base_file {
+                their_file.cloned()
+            } else if let (Some(our_file), Some(their_file)) = (our_file, their_file) {
+                // a side that only changed the mode keeps the content of the other.
+                let mode = match base_file {
+                    Some(base_file) if our_file.mode == base_file.mode => their_file.mode,
+                    _ => our_file.mode,
+                };
+                let (our_hash, their_hash) = (&our_file.hash, &their_file.hash);
+                let base_hash = base_file.map(|x| &x.hash);
+                let result_hash = if our_hash == their_hash || Some(their_hash) == base_hash {
+                    Some(our_hash.clone())
+                } else if Some(our_hash) == base_hash {
+                    Some(their_hash.clone())
+                } else {
+                    None
+                };
+                if let Some(hash) = result_hash {
+                    merged.insert(path.clone(), FileEntry::new(&hash, mode));
+                    continue;
+                }
+
+                let base_content = match base_hash {
+                    Some(hash) => self.read_blob_content(hash)?,
+                    None => Default::default(),
+                };
+                let our_content = self.read_blob_content(our_hash)?;
+                let their_content = self.read_blob_content(their_hash)?;
+                let texts = (
+                    String::from_utf8(base_content),
+                    String::from_utf8(our_content),
+                    String::from_utf8(their_content),
+                );
+                let is_link = [Some(our_file), Some(their_file), base_file]
+                    .into_iter()
+                    .flatten()
+                    .any(|x| x.mode == FileMode::Symlink);
+                if let (false, (Ok(base_text), Ok(our_text), Ok(their_text))) = (is_link, texts) {
+                    let chunks = merge_lines(&base_text, &our_text, &their_text);
+                    let (content, has_conflict) = write_conflict_markers(chunks, branch);
+                    if has_conflict {
+                        conflicts.push(MergeConflict {
+                            path: path.clone(),
+                            content: Some(content),
+                        });
+                        Some(our_file.clone())
+                    } else {
+                        let hash = self.write_blob(content.into_bytes())?;
+                        Some(FileEntry::new(&hash, mode))
+                    }
+                } else {
+                    // binary files and link targets cannot be merged line by line, our
+                    // version stays in place.
+                    conflicts.push(MergeConflict {
+                        path: path.clone(),
+                        content: None,
+                    });
+                    Some(our_file.clone())
+                }
+            } else {
+                // deleted on one side and modified on the other.
+                conflicts.push(MergeConflict {
+                    path: path.clone(),
+                    content: None,
+                });
+                our_file.or(their_file).cloned()
+            };
+
+            if let Some(file) = result {
+                merged.insert(path.clone(), file);
+            }
+        }
+
+        Ok(MergeResult {
+            files: merged,
+            conflicts,
+        })
+    }
+}
+
+/// Joins the merged chunks, surrounding each conflict with git style markers.
+fn write_conflict_markers(chunks: Vec<MergeChunk>, branch: &str) -> (String, bool) {
+    let mut content: String = Default::default();
+    let mut has_conflict = false;
+    for chunk in chunks {
+        match chunk {
+            MergeChunk::Resolved(lines) => content.push_str(lines.concat().as_str()),
+            MergeChunk::Conflict { ours, theirs } => {
+                has_conflict = true;
+                content.push_str("<<<<<<< HEAD\n");
+                push_lines(&mut content, ours);
+                content.push_str("=======\n");
+                push_lines(&mut content, theirs);
+                content.push_str((">>>>>>> ".to_string() + branch + "\n").as_str());
+            }
+        }
+    }
+
+    (content, has_conflict)
+}
+
+fn push_lines(content: &mut String, lines: Vec<&str>) {
+    content.push_str(lines.concat().as_str());
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+}
+
+fn get_hunks<'a>(base: &[&'a str], other: &[&'a str], ours: bool) -> Vec<Hunk<'a>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        if let DiffOp::Equal { .. } = op {
+            continue;
+        }
+        let old_range = op.old_range();
+        hunks.push(Hunk {
+            start: old_range.start,
+            end: old_range.end,
+            lines: other[op.new_range()].to_vec(),
+            ours,
+        });
+    }
+
+    hunks
+}
+
+/// Applies the hunks of one side to the base lines `start..end`.
+fn apply_hunks<'a>(
+    base: &[&'a str],
+    start: usize,
+    end: usize,
+    hunks: &[&Hunk<'a>],
+) -> Vec<&'a str> {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut position = start;
+    for hunk in hunks {
+        lines.extend_from_slice(&base[position..hunk.start]);
+        lines.extend_from_slice(&hunk.lines);
+        position = hunk.end;
+    }
+    lines.extend_from_slice(&base[position..end]);
+
+    lines
+}
+
+/// Line based three-way merge. Changes of both sides that touch the same base lines are
+/// returned as conflicts, everything else is combined.
+pub fn merge_lines<'a>(base: &'a str, ours: &'a str, theirs: &'a str) -> Vec<MergeChunk<'a>> {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let our_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let their_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let mut hunks = get_hunks(&base_lines, &our_lines, true);
+    hunks.extend(get_hunks(&base_lines, &their_lines, false));
+    hunks.sort_by_key(|x| (x.start, x.end));
+
+    let mut chunks: Vec<MergeChunk> = Vec::new();
+    let mut position = 0;
+    let mut index = 0;
+    while index < hunks.len() {
+        // group every hunk that overlaps or touches the previous ones.
+        let start = hunks[index].start;
+        let mut end = hunks[index].end;
+        let mut group: Vec<&Hunk> = vec![&hunks[index]];
+        index += 1;
+        while index < hunks.len() && hunks[index].start <= end {
+            end = end.max(hunks[index].end);
+            group.push(&hunks[index]);
+            index += 1;
+        }
+
+        chunks.push(MergeChunk::Resolved(base_lines[position..start].to_vec()));
+        let our_hunks: Vec<&Hunk> = group.iter().copied().filter(|x| x.ours).collect();
+        let their_hunks: Vec<&Hunk> = group.iter().copied().filter(|x| !x.ours).collect();
+        let our_version = apply_hunks(&base_lines, start, end, &our_hunks);
+        let their_version = apply_hunks(&base_lines, start, end, &their_hunks);
+        if our_hunks.is_empty() {
+            chunks.push(MergeChunk::Resolved(their_version));
+        } else if their_hunks.is_empty() || our_version == their_version {
+            chunks.push(MergeChunk::Resolved(our_version));
+        } else {
+            chunks.push(MergeChunk::Conflict {
+                ours: our_version,
+                theirs: their_version,
+            });
+        }
+        position = end;
+    }
+    chunks.push(MergeChunk::Resolved(base_lines[position..].to_vec()));
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Commit, Object};
+    use crate::testing::TemporaryRepository;
+
+    fn commit(repository: &Repository, message: &str, parents: &[&String]) -> String {
+        let commit = Commit {
+            tree: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string(),
+            parents: parents.iter().map(|x| x.to_string()).collect(),
+            message: message.to_string(),
+        };
+        repository.objects().write(&Object::Commit(commit)).unwrap()
+    }
+
+    #[test]
+    fn finds_the_lowest_common_ancestor() {
+        let repository = TemporaryRepository::new("merge", "base");
+        let root = commit(&repository, "root", &[]);
+        let base = commit(&repository, "base", &[&root]);
+        let head = commit(&repository, "head", &[&base]);
+        let side = commit(&repository, "side", &[&base]);
+        // the root is found first walking from the branch, but it is an ancestor of base.
+        let branch = commit(&repository, "merge root", &[&root, &side]);
+
+        let merge_base = repository.merge_base(&head, &branch).unwrap();
+
+        assert_eq!(merge_base, Some(base.clone()));
+        let merge_base = repository.merge_base(&branch, &head).unwrap();
+        assert_eq!(merge_base, Some(base));
+    }
+
+    #[test]
+    fn finds_no_base_for_unrelated_histories() {
+        let repository = TemporaryRepository::new("merge", "unrelated");
+        let first = commit(&repository, "first", &[]);
+        let second = commit(&repository, "second", &[]);
+
+        assert_eq!(repository.merge_base(&first, &second).unwrap(), None);
+        let child = commit(&repository, "child", &[&first]);
+        let merge_base = repository.merge_base(&child, &first).unwrap();
+        assert_eq!(merge_base, Some(first));
+    }
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, bool) {
+        write_conflict_markers(merge_lines(base, ours, theirs), "side")
+    }
+
+    #[test]
+    fn combines_changes_to_different_lines() {
+        let base = "a\nb\nc\nd\n";
+        let merged = merge(base, "A\nb\nc\nd\n", "a\nb\nc\nD\n");
+
+        assert_eq!(merged, ("A\nb\nc\nD\n".to_string(), false));
+    }
+
+    #[test]
+    fn keeps_a_change_made_on_both_sides_once() {
+        let merged = merge("a\nb\n", "a\nB\n", "a\nB\n");
+
+        assert_eq!(merged, ("a\nB\n".to_string(), false));
+    }
+
+    #[test]
+    fn takes_lines_added_and_removed_by_one_side() {
+        assert_eq!(merge("a\nb\n", "a\nb\n", "a\nx\nb\n").0, "a\nx\nb\n");
+        assert_eq!(merge("a\nb\nc\n", "a\nc\n", "a\nb\nc\n").0, "a\nc\n");
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        let merged = merge("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n");
+
+        assert_eq!(
+            merged,
+            (
+                "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> side\nc\n".to_string(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn ends_conflicting_lines_without_newline() {
+        let merged = merge("a\n", "a\nours", "a\ntheirs");
+
+        assert_eq!(
+            merged.0,
+            "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> side\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_a_conflicting_link_as_a_link() {
+        let repository = TemporaryRepository::new("merge", "link");
+        let link = |target: &str| {
+            let _ = fs::remove_file(repository.work_path("l"));
+            std::os::unix::fs::symlink(target, repository.work_path("l")).unwrap();
+        };
+        link("target1");
+        repository.commit_files(&["l"], "base");
+        repository.checkout("feature", true, false).unwrap();
+        link("target2");
+        repository.commit_files(&["l"], "feature");
+        repository.checkout("main", false, false).unwrap();
+        link("target3");
+        repository.commit_files(&["l"], "main");
+
+        let outcome = repository.merge("feature", None, false).unwrap();
+        assert!(matches!(outcome, MergeOutcome::Conflicts { .. }));
+        let commit = repository.continue_merge().unwrap();
+
+        let files = repository.read_commit_files(Some(commit.as_str())).unwrap();
+        assert_eq!(files["./l"].mode, FileMode::Symlink);
+        assert_eq!(
+            repository.read_blob_content(&files["./l"].hash).unwrap(),
+            b"target3"
+        );
+    }
+}
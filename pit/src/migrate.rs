@@ -0,0 +1,439 @@
+use crate::error::{Error, Result};
+use crate::index::{Index, IndexEntry};
+use crate::merge::MERGE_HEAD;
+use crate::object::{is_valid_entry_name, parse_legacy_blob, Blob, Object, ObjectKind};
+use crate::refs::Ref;
+use crate::repository::{
+    normalize_path, read_format_version, FileEntry, Repository, FORMAT_FILE, FORMAT_VERSION,
+    PIT_FOLDER,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The text file formats 1 and 2 staged files in.
+const LEGACY_STAGING_FILE: &str = "staging";
+
+/// What `Repository::migrate` did.
+#[derive(Debug)]
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+    /// Number of objects rewritten in the new format, or moved when only the layout changed.
+    pub objects: usize,
+}
+
+/// The format 0 objects already rewritten.
+#[derive(Default)]
+struct Rewritten {
+    /// Old hash -> new hash.
+    hashes: HashMap<String, String>,
+    /// Old blob hash -> path the blob was added from, as stored in the blob.
+    paths: HashMap<String, String>,
+}
+
+impl Repository {
+    /// Upgrades a repository written by an older pit to the current object format.
+    /// Everything reachable from the branches, the merge in progress and the staged files
+    /// is rewritten and the old objects are removed.
+    pub fn migrate(root: &Path) -> Result<Migration> {
+        if !root.join(PIT_FOLDER).is_dir() {
+            return Err(Error::NotARepository);
+        }
+        let from = read_format_version(root)?;
+        if from > FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat(from.to_string()));
+        }
+        let repository = Repository::new(root);
+        let mut migration = Migration {
+            from,
+            to: FORMAT_VERSION,
+            objects: 0,
+        };
+        if from == FORMAT_VERSION {
+            return Ok(migration);
+        }
+
+        // the layout is fixed first so the rewrite below finds the objects where it expects.
+        migration.objects = repository.shard_objects()?;
+        if from == 0 {
+            migration.objects = repository.rewrite_legacy_objects()?;
+        }
+        repository.convert_staging_file()?;
+        fs::write(
+            repository.pit_path().join(FORMAT_FILE),
+            FORMAT_VERSION.to_string(),
+        )?;
+
+        Ok(migration)
+    }
+
+    /// Format 1 stored every object directly in `.pit/objects`, next to the staged files.
+    /// Moves the objects into `objects/<2 characters>/<38 characters>` and the staged files
+    /// to `.pit/staging`. Returns the number of objects moved.
+    fn shard_objects(&self) -> Result<usize> {
+        let mut moved = 0;
+        for entry in fs::read_dir(self.objects().path())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if name == "info" {
+                fs::rename(entry.path(), self.pit_path().join(LEGACY_STAGING_FILE))?;
+                continue;
+            }
+            if name.len() != 40 || !name.chars().all(|x| x.is_ascii_hexdigit()) {
+                continue;
+            }
+            let object_path = self.objects().object_path(name.as_str());
+            if let Some(parent) = object_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(entry.path(), object_path)?;
+            moved += 1;
+        }
+
+        Ok(moved)
+    }
+
+    /// Format 0 blobs stored their path after the content. Rewrites everything reachable from
+    /// the branches, the merge in progress and the staged files, and returns the number of
+    /// objects rewritten. Every rewritten commit must hold the files its blobs were added
+    /// from, otherwise nothing is changed.
+    fn rewrite_legacy_objects(&self) -> Result<usize> {
+        let mut migrated = Rewritten::default();
+        let mut branches: Vec<(Ref, String)> = Vec::new();
+        for branch in self.branches()? {
+            if let Some(commit) = self.read_ref(&branch)? {
+                let commit = self.migrate_commit(commit.as_str(), &mut migrated)?;
+                branches.push((branch, commit));
+            }
+        }
+        let merge_head_path = self.pit_path().join(MERGE_HEAD);
+        let merge_head = match fs::read_to_string(&merge_head_path) {
+            Ok(commit) => Some(self.migrate_commit(commit.trim(), &mut migrated)?),
+            Err(_) => None,
+        };
+        // format 0 staged one blob hash per line and took the path from the blob.
+        let info_path = self.pit_path().join(LEGACY_STAGING_FILE);
+        let mut staged: Vec<String> = Vec::new();
+        for hash in fs::read_to_string(&info_path).unwrap_or_default().lines() {
+            if hash.is_empty() {
+                continue;
+            }
+            let (content, path) = parse_legacy_blob(hash, &self.objects().read_raw(hash)?)?;
+            let new_hash = self.write_blob(content)?;
+            migrated.hashes.insert(hash.to_string(), new_hash.clone());
+            staged.push(new_hash + " " + normalize_path(path.as_str()).as_str());
+            migrated.paths.insert(hash.to_string(), path);
+        }
+
+        // nothing is changed until every object was rewritten.
+        for (branch, commit) in &branches {
+            self.write_ref(branch, commit.as_str())?;
+        }
+        if let Some(commit) = merge_head {
+            fs::write(&merge_head_path, commit)?;
+        }
+        fs::write(&info_path, staged.join("\n"))?;
+
+        for (old_hash, new_hash) in &migrated.hashes {
+            if old_hash != new_hash {
+                fs::remove_file(self.objects().object_path(old_hash))?;
+            }
+        }
+
+        Ok(migrated.hashes.len())
+    }
+
+    /// Formats 1 and 2 staged files in a text file with one `<hash> <path>` line per file.
+    /// Moves them to the index. The working files are not looked at, so the stat data of
+    /// the new entries is empty and status hashes those files again.
+    fn convert_staging_file(&self) -> Result<()> {
+        let staging_path = self.pit_path().join(LEGACY_STAGING_FILE);
+        let content = match fs::read_to_string(&staging_path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => Default::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut index = Index::new(self.index_path());
+        for line in content.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            // the hash never contains a space, the path may.
+            let (hash, path) = line
+                .split_once(' ')
+                .ok_or_else(|| Error::CorruptIndex(staging_path.display().to_string()))?;
+            index.insert(path, IndexEntry::without_stat(&FileEntry::regular(hash)));
+        }
+        index.save()?;
+        if staging_path.exists() {
+            fs::remove_file(&staging_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites a commit and its history, parents first.
+    fn migrate_commit(&self, commit: &str, migrated: &mut Rewritten) -> Result<String> {
+        // a loop instead of recursion so long histories do not overflow the stack.
+        let mut stack: Vec<String> = vec![commit.to_string()];
+        while let Some(hash) = stack.pop() {
+            if migrated.hashes.contains_key(&hash) {
+                continue;
+            }
+            let mut commit = self.objects().read_commit(hash.as_str())?;
+            let missing: Vec<String> = commit
+                .parents
+                .iter()
+                .filter(|x| !migrated.hashes.contains_key(*x))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                stack.push(hash);
+                stack.extend(missing);
+                continue;
+            }
+
+            let old_tree = commit.tree.clone();
+            commit.tree = self.migrate_tree(old_tree.as_str(), migrated)?;
+            self.check_migrated_tree(old_tree.as_str(), commit.tree.as_str(), migrated)?;
+            commit.parents = commit
+                .parents
+                .iter()
+                .map(|x| migrated.hashes[x].clone())
+                .collect();
+            let new_hash = self.objects().write(&Object::Commit(commit))?;
+            migrated.hashes.insert(hash, new_hash);
+        }
+
+        Ok(migrated.hashes[commit].clone())
+    }
+
+    /// Blobs are named after the path stored in them rather than the tree line, which old
+    /// trees did not always write in full.
+    fn migrate_tree(&self, tree: &str, migrated: &mut Rewritten) -> Result<String> {
+        if let Some(new_hash) = migrated.hashes.get(tree) {
+            return Ok(new_hash.clone());
+        }
+        let mut new_tree = self.objects().read_tree(tree)?;
+        for entry in &mut new_tree.entries {
+            let old_hash = entry.hash.clone();
+            entry.hash = match entry.kind() {
+                ObjectKind::Tree => self.migrate_tree(old_hash.as_str(), migrated)?,
+                ObjectKind::Blob => self.migrate_blob(old_hash.as_str(), migrated)?,
+                ObjectKind::Commit => old_hash.clone(),
+            };
+            if let Some(path) = migrated.paths.get(&old_hash) {
+                let path = normalize_path(path.as_str());
+                entry.name = path.rsplit('/').next().unwrap_or_default().to_string();
+            }
+            if !is_valid_entry_name(entry.name.as_str()) {
+                return Err(Error::CorruptObject(tree.to_string()));
+            }
+        }
+        let new_hash = self.objects().write(&Object::Tree(new_tree))?;
+        migrated.hashes.insert(tree.to_string(), new_hash.clone());
+
+        Ok(new_hash)
+    }
+
+    fn migrate_blob(&self, blob: &str, migrated: &mut Rewritten) -> Result<String> {
+        if let Some(new_hash) = migrated.hashes.get(blob) {
+            return Ok(new_hash.clone());
+        }
+        let (content, path) = parse_legacy_blob(blob, &self.objects().read_raw(blob)?)?;
+        let new_hash = self.objects().write(&Object::Blob(Blob { content }))?;
+        migrated.hashes.insert(blob.to_string(), new_hash.clone());
+        migrated.paths.insert(blob.to_string(), path);
+
+        Ok(new_hash)
+    }
+
+    /// Fails unless the rewritten tree holds exactly the blobs of the old one, each at the
+    /// path stored in the old blob.
+    fn check_migrated_tree(
+        &self,
+        old_tree: &str,
+        new_tree: &str,
+        migrated: &Rewritten,
+    ) -> Result<()> {
+        let mut expected: BTreeMap<String, String> = BTreeMap::new();
+        let mut stack: Vec<String> = vec![old_tree.to_string()];
+        while let Some(tree) = stack.pop() {
+            for entry in self.objects().read_tree(tree.as_str())?.entries {
+                match entry.kind() {
+                    ObjectKind::Tree => stack.push(entry.hash),
+                    ObjectKind::Blob => {
+                        let path = normalize_path(migrated.paths[&entry.hash].as_str());
+                        expected.insert(path, migrated.hashes[&entry.hash].clone());
+                    }
+                    ObjectKind::Commit => {}
+                }
+            }
+        }
+        let found: BTreeMap<String, String> = self
+            .read_tree_files(new_tree)?
+            .into_iter()
+            .map(|(path, file)| (path, file.hash))
+            .collect();
+        if found != expected {
+            return Err(Error::CorruptObject(old_tree.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::hash_content;
+    use crate::testing::TemporaryFolder;
+    use std::path::PathBuf;
+
+    /// A repository as the first pit wrote it: objects named after the hash of their raw
+    /// text, directly in `.pit/objects`, and the staged blobs listed in `objects/info`.
+    struct LegacyRepository {
+        root: PathBuf,
+        _folder: TemporaryFolder,
+    }
+
+    impl LegacyRepository {
+        fn new(name: &str) -> Self {
+            let folder = TemporaryFolder::new("migrate", name);
+            let root = folder.path().to_path_buf();
+            fs::create_dir_all(root.join(".pit/objects")).unwrap();
+            fs::create_dir_all(root.join(".pit/refs")).unwrap();
+            fs::write(root.join(".pit/HEAD"), "refs/main").unwrap();
+            fs::write(root.join(".pit/objects/info"), "").unwrap();
+            LegacyRepository {
+                root,
+                _folder: folder,
+            }
+        }
+
+        fn write(&self, content: String) -> String {
+            let hash = hash_content(content.as_bytes());
+            fs::write(self.root.join(".pit/objects").join(&hash), content).unwrap();
+            hash
+        }
+
+        fn blob(&self, path: &str, content: &str) -> String {
+            self.write(format!("{}\n\n{}\n\nblob", content, path))
+        }
+
+        /// `entries` are (type, hash, line) with the line as the old tree wrote it.
+        fn tree(&self, path: &str, entries: &[(&str, &str, &str)]) -> String {
+            let mut content = String::new();
+            for (kind, hash, line) in entries {
+                content += format!("{} {} {}\n\n", kind, hash, line).as_str();
+            }
+            self.write(content + path + "\n\ntree")
+        }
+
+        fn commit(&self, tree: &str, parent: &str, message: &str) -> String {
+            let content = format!("tree {}\nparent {}\n\n{}\n\ncommit", tree, parent, message);
+            let hash = self.write(content);
+            fs::write(self.root.join(".pit/refs/main"), &hash).unwrap();
+            hash
+        }
+
+        fn files(&self) -> BTreeMap<String, Vec<u8>> {
+            let repository = Repository::open(&self.root).unwrap();
+            let head = repository.head_commit().unwrap();
+            let files = repository.read_commit_files(head.as_deref()).unwrap();
+            files
+                .into_iter()
+                .map(|(path, file)| (path, repository.read_blob_content(&file.hash).unwrap()))
+                .collect()
+        }
+    }
+
+    fn expected(files: &[(&str, &str)]) -> BTreeMap<String, Vec<u8>> {
+        files
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn migrates_the_history_of_a_baseline_repository() {
+        let repository = LegacyRepository::new("baseline");
+        let a = repository.blob("./a.txt", "hi");
+        let b = repository.blob("./d/b c.txt", "x");
+        let d = repository.tree("./d", &[("blob", &b, "./d/b c.txt")]);
+        let root = repository.tree(".", &[("blob", &a, "./a.txt"), ("tree", &d, "./d")]);
+        let first = repository.commit(&root, "", "first");
+        let a = repository.blob("./a.txt", "hi\nmore");
+        let n = repository.blob("./d/n.txt", "new");
+        let d = repository.tree(
+            "./d",
+            &[("blob", &b, "./d/b c.txt"), ("blob", &n, "./d/n.txt")],
+        );
+        let root = repository.tree(".", &[("blob", &a, "./a.txt"), ("tree", &d, "./d")]);
+        let second = repository.commit(&root, &first, "second");
+
+        let migration = Repository::migrate(&repository.root).unwrap();
+
+        assert_eq!((migration.from, migration.to), (0, FORMAT_VERSION));
+        assert_eq!(
+            repository.files(),
+            expected(&[
+                ("./a.txt", "hi\nmore"),
+                ("./d/b c.txt", "x"),
+                ("./d/n.txt", "new")
+            ])
+        );
+        let migrated = Repository::open(&repository.root).unwrap();
+        let log = migrated
+            .log(&migrated.head_commit().unwrap().unwrap())
+            .unwrap();
+        let messages: Vec<&str> = log.iter().map(|x| x.commit.message.as_str()).collect();
+        assert_eq!(messages, ["second", "first"]);
+        assert!(!repository
+            .root
+            .join(".pit/objects")
+            .join(&second[..2])
+            .join(&second[2..])
+            .exists());
+    }
+
+    #[test]
+    fn names_entries_whose_path_is_on_the_next_line() {
+        let repository = LegacyRepository::new("next-line");
+        let a = repository.blob("./a.txt", "hi");
+        let b = repository.blob("./d/b.txt", "x");
+        let d = repository.tree("./d", &[("blob", &b, "./\n./d/b.txt")]);
+        let root = repository.tree(".", &[("blob", &a, "./\n./a.txt"), ("tree", &d, "./d")]);
+        repository.commit(&root, "", "first");
+
+        Repository::migrate(&repository.root).unwrap();
+
+        assert_eq!(
+            repository.files(),
+            expected(&[("./a.txt", "hi"), ("./d/b.txt", "x")])
+        );
+    }
+
+    #[test]
+    fn keeps_the_old_objects_when_a_tree_loses_files() {
+        let repository = LegacyRepository::new("mismatch");
+        let b = repository.blob("./d/b.txt", "x");
+        // the blob was added from ./d but the tree lists it at the top.
+        let root = repository.tree(".", &[("blob", &b, "./b.txt")]);
+        let commit = repository.commit(&root, "", "first");
+
+        assert!(Repository::migrate(&repository.root).is_err());
+
+        let main = fs::read_to_string(repository.root.join(".pit/refs/main")).unwrap();
+        assert_eq!(main, commit);
+        let objects = repository.root.join(".pit/objects");
+        for hash in [&b, &root, &commit] {
+            assert!(objects.join(&hash[..2]).join(&hash[2..]).exists());
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use crate::error::{Error, Result};
+use chksum_sha1 as sha1;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+/// Size of the buffer files are read through when they are hashed or stored.
+pub(crate) const BUFFER_SIZE: usize = 64 * 1024;
+/// The longest header is "commit " followed by a 20 digit size and NUL.
+pub(crate) const HEADER_MAX_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<ObjectKind> {
+        match kind {
+            "blob" => Some(ObjectKind::Blob),
+            "tree" => Some(ObjectKind::Tree),
+            "commit" => Some(ObjectKind::Commit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The mode of a tree entry, written in front of it as git does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileMode {
+    Regular,
+    Executable,
+    /// A symbolic link, whose blob holds the target path.
+    Symlink,
+    Tree,
+    /// A commit of another repository.
+    Commit,
+}
+
+impl FileMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileMode::Regular => "100644",
+            FileMode::Executable => "100755",
+            FileMode::Symlink => "120000",
+            FileMode::Tree => "40000",
+            FileMode::Commit => "160000",
+        }
+    }
+
+    pub fn parse(mode: &str) -> Option<FileMode> {
+        match mode {
+            "100644" => Some(FileMode::Regular),
+            "100755" => Some(FileMode::Executable),
+            "120000" => Some(FileMode::Symlink),
+            "40000" => Some(FileMode::Tree),
+            "160000" => Some(FileMode::Commit),
+            _ => None,
+        }
+    }
+
+    /// The mode as a number, as stored in the index.
+    pub fn bits(&self) -> u32 {
+        u32::from_str_radix(self.as_str(), 8).unwrap_or(0)
+    }
+
+    pub fn from_bits(bits: u32) -> Option<FileMode> {
+        FileMode::parse(format!("{:o}", bits).as_str())
+    }
+
+    /// The kind of object the entry points to.
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            FileMode::Regular | FileMode::Executable | FileMode::Symlink => ObjectKind::Blob,
+            FileMode::Tree => ObjectKind::Tree,
+            FileMode::Commit => ObjectKind::Commit,
+        }
+    }
+
+    /// The mode of entries written before modes were kept, which only had a type.
+    fn from_kind(kind: ObjectKind) -> FileMode {
+        match kind {
+            ObjectKind::Blob => FileMode::Regular,
+            ObjectKind::Tree => FileMode::Tree,
+            ObjectKind::Commit => FileMode::Commit,
+        }
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// File content. The path only lives in the tree entries, so equal files share one blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub content: Vec<u8>,
+}
+
+/// A file or folder inside a tree. The name is a single path component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: FileMode,
+    pub hash: String,
+    pub name: String,
+}
+
+impl TreeEntry {
+    pub fn kind(&self) -> ObjectKind {
+        self.mode.kind()
+    }
+}
+
+/// One folder of a snapshot. Folders with the same content share one tree wherever they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+}
+
+impl Object {
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Blob(_) => ObjectKind::Blob,
+            Object::Tree(_) => ObjectKind::Tree,
+            Object::Commit(_) => ObjectKind::Commit,
+        }
+    }
+
+    /// Every object starts with a `<type> <size>\0` header, the size being the length in
+    /// bytes of the body that follows. Tree entries are written as `<mode> <name>\0` followed
+    /// by the 20 bytes of the hash, so names may hold any character but `/` and NUL.
+    /// Commits end with their message.
+    pub fn serialize(&self) -> Vec<u8> {
+        let body = self.serialize_body();
+        let header = self.kind().as_str().to_owned() + " " + body.len().to_string().as_str();
+        let mut content = header.into_bytes();
+        content.push(0);
+        content.extend(body);
+
+        content
+    }
+
+    fn serialize_body(&self) -> Vec<u8> {
+        let mut content: String = Default::default();
+        match self {
+            Object::Blob(blob) => return blob.content.clone(),
+            Object::Tree(tree) => {
+                let mut body: Vec<u8> = Vec::new();
+                for entry in &tree.entries {
+                    body.extend(entry.mode.as_str().as_bytes());
+                    body.push(b' ');
+                    body.extend(entry.name.as_bytes());
+                    body.push(0);
+                    body.extend(decode_hash(entry.hash.as_str()));
+                }
+                return body;
+            }
+            Object::Commit(commit) => {
+                content.push_str(("tree ".to_owned() + &commit.tree + "\n").as_str());
+                if commit.parents.is_empty() {
+                    content.push_str("parent \n");
+                }
+                for parent in &commit.parents {
+                    content.push_str(("parent ".to_owned() + parent + "\n").as_str());
+                }
+                content.push('\n');
+                content.push_str(commit.message.as_str());
+            }
+        }
+
+        content.into_bytes()
+    }
+
+    pub fn hash(&self) -> String {
+        hash_content(&self.serialize())
+    }
+
+    /// Parses an object read from `hash`. The hash is only used for error messages.
+    /// Objects written before the header existed end with `\n\n<type>` instead and are
+    /// still accepted.
+    pub fn parse(hash: &str, content: &[u8]) -> Result<Object> {
+        let corrupt = || Error::CorruptObject(hash.to_string());
+        let (kind, body) = match split_header(content) {
+            Some(object) => object,
+            None => {
+                // the old format only stored text.
+                let content = std::str::from_utf8(content).map_err(|_| corrupt())?;
+                let (rest, kind) = content.rsplit_once("\n\n").ok_or_else(corrupt)?;
+                (
+                    ObjectKind::parse(kind).ok_or_else(corrupt)?,
+                    rest.as_bytes(),
+                )
+            }
+        };
+
+        match kind {
+            ObjectKind::Blob => Ok(Object::Blob(Blob {
+                content: body.to_vec(),
+            })),
+            ObjectKind::Tree => {
+                // trees written before entries ended with NUL hold text lines.
+                let entries = if body.is_empty() || body.contains(&0) {
+                    parse_tree_entries(body).ok_or_else(corrupt)?
+                } else {
+                    let rest = std::str::from_utf8(body).map_err(|_| corrupt())?;
+                    parse_legacy_tree_entries(rest).ok_or_else(corrupt)?
+                };
+                Ok(Object::Tree(Tree { entries }))
+            }
+            ObjectKind::Commit => {
+                let rest = std::str::from_utf8(body).map_err(|_| corrupt())?;
+                let (header, message) = rest.split_once("\n\n").unwrap_or((rest, ""));
+                let mut lines = header.lines(); // first line tree, then one parent line per parent.
+                let tree = lines
+                    .next()
+                    .and_then(|x| x.strip_prefix("tree "))
+                    .ok_or_else(corrupt)?;
+                let mut parents: Vec<String> = Vec::new();
+                for line in lines {
+                    let parent = line.strip_prefix("parent").ok_or_else(corrupt)?.trim();
+                    if !parent.is_empty() {
+                        parents.push(parent.to_string());
+                    }
+                }
+                Ok(Object::Commit(Commit {
+                    tree: tree.trim().to_string(),
+                    parents,
+                    message: message.to_string(),
+                }))
+            }
+        }
+    }
+}
+
+fn parse_tree_entries(mut body: &[u8]) -> Option<Vec<TreeEntry>> {
+    let mut entries: Vec<TreeEntry> = Vec::new();
+    while !body.is_empty() {
+        let space = body.iter().position(|x| *x == b' ')?;
+        let mode = FileMode::parse(std::str::from_utf8(&body[..space]).ok()?)?;
+        body = &body[space + 1..];
+        let end = body.iter().position(|x| *x == 0)?;
+        let name = std::str::from_utf8(&body[..end]).ok()?.to_string();
+        // a `..` or `a/b` entry would be written outside its folder on checkout.
+        if !is_valid_entry_name(name.as_str()) {
+            return None;
+        }
+        let hash = body.get(end + 1..end + 21)?;
+        entries.push(TreeEntry {
+            mode,
+            hash: encode_hash(hash),
+            name,
+        });
+        body = &body[end + 21..];
+    }
+
+    Some(entries)
+}
+
+/// Old trees hold `<type> <hash> <full path>` entries separated by blank lines, followed by
+/// the path of the tree. The path may also start on the line after the hash.
+fn parse_legacy_tree_entries(content: &str) -> Option<Vec<TreeEntry>> {
+    let (blocks, _) = content.rsplit_once("\n\n").unwrap_or(("", content));
+    let mut entries: Vec<TreeEntry> = Vec::new();
+    for block in blocks.split("\n\n") {
+        if block.is_empty() {
+            continue;
+        }
+        // the path is last, so it keeps any space it contains.
+        let data: Vec<&str> = block.splitn(3, ' ').collect();
+        if data.len() != 3 {
+            continue;
+        }
+        let path = match data[2].split_once('\n') {
+            Some((first, rest)) if normalize_legacy_path(first).is_empty() => rest,
+            _ => data[2],
+        };
+        let name = normalize_legacy_path(path).rsplit('/').next()?.to_string();
+        if !is_valid_entry_name(name.as_str()) {
+            return None;
+        }
+        entries.push(TreeEntry {
+            mode: FileMode::from_kind(ObjectKind::parse(data[0])?),
+            hash: data[1].to_string(),
+            name,
+        });
+    }
+
+    Some(entries)
+}
+
+/// Drops the `./` and trailing slashes old trees put around paths.
+fn normalize_legacy_path(path: &str) -> &str {
+    let path = path.trim_end_matches('/');
+    let path = path.strip_prefix("./").unwrap_or(path);
+    if path == "." {
+        ""
+    } else {
+        path
+    }
+}
+
+/// Tree entry names are a single path component: never empty, `.`, `..` or holding a `/`.
+pub fn is_valid_entry_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\0')
+}
+
+pub(crate) fn decode_hash(hash: &str) -> Vec<u8> {
+    (0..hash.len() / 2)
+        .map(|x| u8::from_str_radix(&hash[x * 2..x * 2 + 2], 16).unwrap_or(0))
+        .collect()
+}
+
+pub(crate) fn encode_hash(hash: &[u8]) -> String {
+    hash.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Splits `<type> <size>\0<body>` into the type and the body. Returns None when the content
+/// does not start with a header or the size does not match, as for objects in the old format.
+fn split_header(content: &[u8]) -> Option<(ObjectKind, &[u8])> {
+    let (kind, size, body_start) = parse_header(content)?;
+    let body = &content[body_start..];
+    if size != body.len() as u64 {
+        return None;
+    }
+
+    Some((kind, body))
+}
+
+/// Reads the `<type> <size>\0` header at the start of `content`. Returns the type, the size
+/// and where the body starts.
+pub(crate) fn parse_header(content: &[u8]) -> Option<(ObjectKind, u64, usize)> {
+    let end = content
+        .iter()
+        .take(HEADER_MAX_LENGTH)
+        .position(|x| *x == 0)?;
+    let header = std::str::from_utf8(&content[..end]).ok()?;
+    let (kind, size) = header.split_once(' ')?;
+
+    Some((ObjectKind::parse(kind)?, size.parse().ok()?, end + 1))
+}
+
+/// Feeds the blob serialization of the file at `path` to `consume`: the header, then the
+/// content read through a fixed-size buffer so the file never has to fit in memory.
+/// A symbolic link is never followed, its blob holds the target path.
+pub(crate) fn read_blob_file(
+    path: &Path,
+    mut consume: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    if fs::symlink_metadata(path)?.file_type().is_symlink() {
+        let target = read_link_target(path)?;
+        consume(format!("blob {}\0", target.len()).as_bytes())?;
+        return consume(&target);
+    }
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    consume(format!("blob {}\0", size).as_bytes())?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut read: u64 = 0;
+    loop {
+        let length = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => length,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        read += length as u64;
+        consume(&buffer[..length])?;
+    }
+    // the header already promised `size` bytes.
+    if read != size {
+        return Err(Error::FileChanged(path.display().to_string()));
+    }
+
+    Ok(())
+}
+
+/// Returns the hash the file at `path` has as a blob, without reading it all in memory.
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = sha1::new();
+    read_blob_file(path, |bytes| {
+        hasher.update(bytes);
+        Ok(())
+    })?;
+
+    Ok(hasher.digest().to_hex_lowercase())
+}
+
+/// Reads the content a working file is stored with: its bytes, or the target path of a
+/// symbolic link.
+pub fn read_work_file(path: &Path) -> Result<Vec<u8>> {
+    if fs::symlink_metadata(path)?.file_type().is_symlink() {
+        return read_link_target(path);
+    }
+
+    Ok(fs::read(path)?)
+}
+
+fn read_link_target(path: &Path) -> Result<Vec<u8>> {
+    let target = fs::read_link(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    }
+    #[cfg(not(unix))]
+    Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+pub fn hash_content(content: &[u8]) -> String {
+    sha1::chksum(content).unwrap().to_hex_lowercase()
+}
+
+/// Reads a blob written before format 1, which stored the path after the content.
+/// Returns the content and the path.
+pub fn parse_legacy_blob(hash: &str, content: &[u8]) -> Result<(Vec<u8>, String)> {
+    let corrupt = || Error::CorruptObject(hash.to_string());
+    let content = std::str::from_utf8(content).map_err(|_| corrupt())?;
+    let rest = content.strip_suffix("\n\nblob").ok_or_else(corrupt)?;
+    let (content, path) = rest.rsplit_once("\n\n").ok_or_else(corrupt)?;
+
+    Ok((content.as_bytes().to_vec(), path.to_string()))
+}
+
+/// Content that is not UTF-8 text or holds a NUL byte is never diffed or merged line by line.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.contains(&0) || std::str::from_utf8(content).is_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+
+    fn entry(mode: FileMode, name: &str) -> TreeEntry {
+        TreeEntry {
+            mode,
+            hash: HELLO.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn blobs_have_the_git_hash() {
+        let blob = Object::Blob(Blob {
+            content: b"hello\n".to_vec(),
+        });
+
+        assert_eq!(blob.hash(), HELLO);
+    }
+
+    #[test]
+    fn trees_round_trip_any_name() {
+        let tree = Object::Tree(Tree {
+            entries: vec![
+                entry(FileMode::Regular, "-rf"),
+                entry(FileMode::Executable, "a b.txt"),
+                entry(FileMode::Symlink, "naïve ✓.txt"),
+            ],
+        });
+        let content = tree.serialize();
+
+        // the hash git mktree gives the same entries.
+        assert_eq!(tree.hash(), "9b31ffd7beadf97c2995be28208d896dc42828e2");
+        assert_eq!(Object::parse(&tree.hash(), &content).unwrap(), tree);
+    }
+
+    #[test]
+    fn commits_round_trip() {
+        let commit = Object::Commit(Commit {
+            tree: HELLO.to_string(),
+            parents: vec![HELLO.to_string(), "0".repeat(40)],
+            message: "Merge\n\nwith a body".to_string(),
+        });
+
+        assert_eq!(Object::parse("", &commit.serialize()).unwrap(), commit);
+    }
+
+    #[test]
+    fn rejects_truncated_trees() {
+        let tree = Object::Tree(Tree {
+            entries: vec![entry(FileMode::Regular, "a")],
+        });
+        let mut content = tree.serialize();
+        content.truncate(content.len() - 5);
+
+        assert!(Object::parse("", &content).is_err());
+    }
+
+    #[test]
+    fn rejects_trees_with_invalid_names() {
+        for name in ["", ".", "..", "a/b"] {
+            let tree = Object::Tree(Tree {
+                entries: vec![entry(FileMode::Regular, name)],
+            });
+
+            assert!(Object::parse("", &tree.serialize()).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn reads_legacy_trees() {
+        let content = "blob ".to_string()
+            + HELLO
+            + " ./d/a b.txt\n\nblob "
+            + HELLO
+            + " ./\n./d/c.txt\n\n./d\n\ntree";
+        let Object::Tree(tree) = Object::parse("", content.as_bytes()).unwrap() else {
+            panic!("not a tree");
+        };
+        let names: Vec<&str> = tree.entries.iter().map(|x| x.name.as_str()).collect();
+
+        assert_eq!(names, ["a b.txt", "c.txt"]);
+    }
+
+    #[test]
+    fn validates_entry_names() {
+        for name in ["a", "-rf", "a b", "naïve", ".hidden", "..."] {
+            assert!(is_valid_entry_name(name), "{}", name);
+        }
+        for name in ["", ".", "..", "a/b", "a\0b"] {
+            assert!(!is_valid_entry_name(name), "{:?}", name);
+        }
+    }
+}
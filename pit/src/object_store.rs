@@ -0,0 +1,294 @@
+use crate::error::{Error, Result};
+use crate::object::{
+    hash_file, parse_header, read_blob_file, read_work_file, Blob, Commit, Object, ObjectKind,
+    Tree, BUFFER_SIZE, HEADER_MAX_LENGTH,
+};
+use crate::pack::PackIndex;
+use crate::repository::{temporary_path, write_file};
+use chksum_sha1 as sha1;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The `.pit/objects` folder. Every object is stored zlib compressed in a file named after the
+/// hash of its uncompressed content, like Git's loose objects. The first two characters of the
+/// hash name a sub folder so no folder grows too large. Objects not found there are looked
+/// up in the packs of `.pit/objects/pack`.
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    path: PathBuf,
+    // pack indexes, loaded on the first lookup that misses the loose objects.
+    packs: Arc<RwLock<Option<Arc<Vec<PackIndex>>>>>,
+}
+
+impl ObjectStore {
+    pub fn new(path: PathBuf) -> Self {
+        ObjectStore {
+            path,
+            packs: Default::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        match (hash.get(..2), hash.get(2..)) {
+            (Some(folder), Some(file)) if !file.is_empty() => self.path.join(folder).join(file),
+            _ => self.path.join(hash),
+        }
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        if hash.is_empty() {
+            return false;
+        }
+        self.object_path(hash).is_file()
+            || self
+                .packs()
+                .is_ok_and(|packs| packs.iter().any(|x| x.contains(hash)))
+    }
+
+    pub fn packs(&self) -> Result<Arc<Vec<PackIndex>>> {
+        if let Some(packs) = self.packs.read().unwrap().as_ref() {
+            return Ok(packs.clone());
+        }
+        let packs = Arc::new(self.load_packs()?);
+        *self.packs.write().unwrap() = Some(packs.clone());
+
+        Ok(packs)
+    }
+
+    /// Forgets the loaded pack indexes after packs were added or removed.
+    pub fn reload_packs(&self) {
+        *self.packs.write().unwrap() = None;
+    }
+
+    /// Returns the uncompressed content of an object without parsing it.
+    pub fn read_raw(&self, hash: &str) -> Result<Vec<u8>> {
+        if hash.is_empty() {
+            return Err(Error::ObjectNotFound(hash.to_string()));
+        }
+        let content = match fs::read(self.object_path(hash)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                for pack in self.packs()?.iter() {
+                    if let Some(content) = pack.read(hash)? {
+                        return Ok(content);
+                    }
+                }
+                return Err(Error::ObjectNotFound(hash.to_string()));
+            }
+            Err(err) => return Err(Error::Io(err)),
+        };
+
+        // objects written before compression was added are stored as they are.
+        let mut decompressed: Vec<u8> = Vec::new();
+        match ZlibDecoder::new(content.as_slice()).read_to_end(&mut decompressed) {
+            Ok(_) => Ok(decompressed),
+            Err(_) => Ok(content),
+        }
+    }
+
+    /// Returns the type and the body size of an object, reading only the start of loose
+    /// objects. Returns None for objects written before the header existed.
+    pub fn read_header(&self, hash: &str) -> Result<Option<(ObjectKind, u64)>> {
+        let content = match File::open(self.object_path(hash)) {
+            Ok(file) => {
+                let mut start: Vec<u8> = Vec::new();
+                let decompressed = ZlibDecoder::new(&file)
+                    .take(HEADER_MAX_LENGTH as u64)
+                    .read_to_end(&mut start);
+                if decompressed.is_err() {
+                    // objects written before compression was added are stored as they are.
+                    start.clear();
+                    (&file).seek(SeekFrom::Start(0))?;
+                    (&file)
+                        .take(HEADER_MAX_LENGTH as u64)
+                        .read_to_end(&mut start)?;
+                }
+                start
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                for pack in self.packs()?.iter() {
+                    if pack.contains(hash) {
+                        return pack.read_header(hash);
+                    }
+                }
+                self.read_raw(hash)?
+            }
+            Err(err) => return Err(Error::Io(err)),
+        };
+
+        Ok(parse_header(&content).map(|(kind, size, _)| (kind, size)))
+    }
+
+    /// Whether a loose object is stored compressed with a header, as packs store it.
+    pub(crate) fn is_compressed(&self, hash: &str) -> Result<bool> {
+        let file = File::open(self.object_path(hash))?;
+        let mut start: Vec<u8> = Vec::new();
+        let decompressed = ZlibDecoder::new(file)
+            .take(HEADER_MAX_LENGTH as u64)
+            .read_to_end(&mut start);
+
+        Ok(decompressed.is_ok() && parse_header(&start).is_some())
+    }
+
+    pub fn read(&self, hash: &str) -> Result<Object> {
+        Object::parse(hash, &self.read_raw(hash)?)
+    }
+
+    pub fn read_blob(&self, hash: &str) -> Result<Blob> {
+        match self.read(hash)? {
+            Object::Blob(blob) => Ok(blob),
+            _ => Err(Error::UnexpectedObject {
+                hash: hash.to_string(),
+                expected: "blob".to_string(),
+            }),
+        }
+    }
+
+    pub fn read_tree(&self, hash: &str) -> Result<Tree> {
+        match self.read(hash)? {
+            Object::Tree(tree) => Ok(tree),
+            _ => Err(Error::UnexpectedObject {
+                hash: hash.to_string(),
+                expected: "tree".to_string(),
+            }),
+        }
+    }
+
+    pub fn read_commit(&self, hash: &str) -> Result<Commit> {
+        match self.read(hash)? {
+            Object::Commit(commit) => Ok(commit),
+            _ => Err(Error::UnexpectedObject {
+                hash: hash.to_string(),
+                expected: "commit".to_string(),
+            }),
+        }
+    }
+
+    /// Writes the content of the blob `hash` to `file_path`. Loose blobs are decompressed
+    /// straight into the file through a fixed-size buffer; packed blobs and blobs written
+    /// before the header existed are read whole first.
+    pub fn copy_blob_to_file(&self, hash: &str, file_path: &Path) -> Result<()> {
+        let file = match File::open(self.object_path(hash)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(fs::write(file_path, self.read_blob(hash)?.content)?);
+            }
+            Err(err) => return Err(Error::Io(err)),
+        };
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, ZlibDecoder::new(file));
+        let mut header: Vec<u8> = Vec::new();
+        let header_read = (&mut reader)
+            .take(HEADER_MAX_LENGTH as u64)
+            .read_until(0, &mut header);
+        let size = match (header_read, parse_header(&header)) {
+            (Ok(_), Some((ObjectKind::Blob, size, _))) => size,
+            (Ok(_), Some(_)) => {
+                return Err(Error::UnexpectedObject {
+                    hash: hash.to_string(),
+                    expected: "blob".to_string(),
+                })
+            }
+            _ => return Ok(fs::write(file_path, self.read_blob(hash)?.content)?),
+        };
+
+        let mut output = File::create(file_path)?;
+        let copied = io::copy(&mut reader, &mut output).map_err(|err| match err.kind() {
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => {
+                Error::CorruptObject(hash.to_string())
+            }
+            _ => Error::Io(err),
+        })?;
+        if copied != size {
+            return Err(Error::CorruptObject(hash.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the object and returns its hash.
+    pub fn write(&self, object: &Object) -> Result<String> {
+        let content = object.serialize();
+        let hash = crate::object::hash_content(&content);
+        if self.contains(hash.as_str()) {
+            return Ok(hash);
+        }
+        let object_path = self.object_path(hash.as_str());
+        if let Some(parent) = object_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_file(&object_path, &compress(&content)?)?;
+
+        Ok(hash)
+    }
+
+    /// Stores the file at `file_path` as a blob and returns its hash. Files larger than the
+    /// read buffer are read through it, once to hash them and, when the blob is not stored
+    /// yet, once more to compress them, so files larger than memory can be added.
+    pub fn write_file_blob(&self, file_path: &Path) -> Result<String> {
+        let metadata = fs::symlink_metadata(file_path)?;
+        if metadata.is_symlink() || metadata.len() <= BUFFER_SIZE as u64 {
+            let content = read_work_file(file_path)?;
+            return self.write(&Object::Blob(Blob { content }));
+        }
+        let hash = hash_file(file_path)?;
+        if self.contains(hash.as_str()) {
+            return Ok(hash);
+        }
+
+        let temporary_path = temporary_path(&self.path.join("blob"));
+        let written = self.write_compressed_file(file_path, &temporary_path, hash.as_str());
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary_path);
+        }
+        written?;
+
+        Ok(hash)
+    }
+
+    fn write_compressed_file(
+        &self,
+        file_path: &Path,
+        temporary_path: &Path,
+        hash: &str,
+    ) -> Result<()> {
+        let mut hasher = sha1::new();
+        let mut encoder = ZlibEncoder::new(
+            BufWriter::new(File::create(temporary_path)?),
+            Compression::default(),
+        );
+        read_blob_file(file_path, |bytes| {
+            hasher.update(bytes);
+            Ok(encoder.write_all(bytes)?)
+        })?;
+        encoder.finish()?.flush()?;
+        // the file changed since it was hashed.
+        if hasher.digest().to_hex_lowercase() != hash {
+            return Err(Error::FileChanged(file_path.display().to_string()));
+        }
+
+        let object_path = self.object_path(hash);
+        if let Some(parent) = object_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(temporary_path, object_path)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn compress(content: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+
+    Ok(encoder.finish()?)
+}
@@ -0,0 +1,633 @@
+use crate::delta::{apply_delta, create_delta};
+use crate::error::{Error, Result};
+use crate::object::{
+    decode_hash, encode_hash, hash_content, parse_header, ObjectKind, HEADER_MAX_LENGTH,
+};
+use crate::object_store::{compress, ObjectStore};
+use crate::repository::{temporary_path, write_file};
+use flate2::read::ZlibDecoder;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+const INDEX_SIGNATURE: &[u8; 4] = b"PIDX";
+const PACK_VERSION: u32 = 1;
+
+const WHOLE: u8 = 0;
+const DELTA: u8 = 1;
+/// How many blobs of a similar size are tried as the base of each blob.
+const DELTA_WINDOW: usize = 10;
+/// Longest chain of deltas, so reading an object never applies more deltas than this.
+const MAX_DELTA_DEPTH: usize = 10;
+/// Larger blobs are never stored as deltas: they are copied into the pack still compressed,
+/// without being held in memory.
+const MAX_DELTA_SIZE: u64 = 16 * 1024 * 1024;
+
+/// What `ObjectStore::repack` did.
+#[derive(Debug, Default)]
+pub struct RepackStats {
+    pub objects: usize,
+    /// Objects stored as a delta against another object of the pack.
+    pub deltas: usize,
+    /// Name of the pack written, None when there was nothing to pack.
+    pub pack: Option<String>,
+    /// Bytes taken by the loose objects and packs before and after repacking.
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// The `.idx` file next to a pack: the offset of every object in the pack, sorted by hash.
+///
+/// A pack starts with `PACK`, the version and the number of objects. Each object is a type
+/// byte (whole or delta), the 20 byte hash of the base for deltas, the length of the data and
+/// the zlib compressed data. The index starts with `PIDX`, the version and the number of
+/// objects, followed by the 20 byte hash and the offset of each object.
+#[derive(Debug, Clone)]
+pub struct PackIndex {
+    pack_path: PathBuf,
+    entries: Vec<(String, u64)>,
+}
+
+impl PackIndex {
+    pub fn load(index_path: &Path) -> Result<PackIndex> {
+        let content = fs::read(index_path)?;
+        let corrupt = || Error::CorruptPack(index_path.display().to_string());
+        if content.get(..4) != Some(INDEX_SIGNATURE.as_slice())
+            || read_u32(&content, 4) != Some(PACK_VERSION)
+        {
+            return Err(corrupt());
+        }
+        let count = read_u32(&content, 8).ok_or_else(corrupt)? as usize;
+        let mut entries: Vec<(String, u64)> = Vec::with_capacity(count);
+        for index in 0..count {
+            let start = 12 + index * 28;
+            let hash = content.get(start..start + 20).ok_or_else(corrupt)?;
+            let offset = read_u64(&content, start + 20).ok_or_else(corrupt)?;
+            entries.push((encode_hash(hash), offset));
+        }
+
+        Ok(PackIndex {
+            pack_path: index_path.with_extension("pack"),
+            entries,
+        })
+    }
+
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.pack_path.with_extension("idx")
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(hash, _)| hash)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.find(hash).is_some()
+    }
+
+    fn find(&self, hash: &str) -> Option<u64> {
+        let index = self
+            .entries
+            .binary_search_by(|(x, _)| x.as_str().cmp(hash))
+            .ok()?;
+
+        Some(self.entries[index].1)
+    }
+
+    /// Returns the uncompressed content of the object, None when it is not in this pack.
+    pub fn read(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let Some(offset) = self.find(hash) else {
+            return Ok(None);
+        };
+        let mut file = File::open(&self.pack_path)?;
+
+        self.read_at(&mut file, hash, offset, 0).map(Some)
+    }
+
+    /// Returns the type and the body size of the object, decompressing only the start of
+    /// objects stored whole. None when it is not in this pack.
+    pub fn read_header(&self, hash: &str) -> Result<Option<(ObjectKind, u64)>> {
+        let corrupt = || Error::CorruptObject(hash.to_string());
+        let start = match self.open_whole(hash)? {
+            Some((file, length)) => {
+                let mut start: Vec<u8> = Vec::new();
+                ZlibDecoder::new(file.take(length))
+                    .take(HEADER_MAX_LENGTH as u64)
+                    .read_to_end(&mut start)
+                    .map_err(|_| corrupt())?;
+                start
+            }
+            None => match self.read(hash)? {
+                Some(content) => content,
+                None => return Ok(None),
+            },
+        };
+        let (kind, size, _) = parse_header(&start).ok_or_else(corrupt)?;
+
+        Ok(Some((kind, size)))
+    }
+
+    /// Opens the pack at the compressed data of an object stored whole, and returns the
+    /// length of that data. None for objects stored as a delta or not in this pack.
+    fn open_whole(&self, hash: &str) -> Result<Option<(File, u64)>> {
+        let Some(offset) = self.find(hash) else {
+            return Ok(None);
+        };
+        let mut file = File::open(&self.pack_path)?;
+        let (base, length) = self.read_entry_header(&mut file, hash, offset)?;
+
+        Ok(base.is_none().then_some((file, length)))
+    }
+
+    /// Reads the type byte, the base hash of a delta and the data length of the object at
+    /// `offset`, leaving `file` at the start of the data.
+    fn read_entry_header(
+        &self,
+        file: &mut File,
+        hash: &str,
+        offset: u64,
+    ) -> Result<(Option<String>, u64)> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut kind = [0u8; 1];
+        file.read_exact(&mut kind)?;
+        let base = match kind[0] {
+            WHOLE => None,
+            DELTA => {
+                let mut base = [0u8; 20];
+                file.read_exact(&mut base)?;
+                Some(encode_hash(&base))
+            }
+            _ => return Err(Error::CorruptObject(hash.to_string())),
+        };
+        let mut length = [0u8; 8];
+        file.read_exact(&mut length)?;
+
+        Ok((base, u64::from_be_bytes(length)))
+    }
+
+    fn read_at(&self, file: &mut File, hash: &str, offset: u64, depth: usize) -> Result<Vec<u8>> {
+        let corrupt = || Error::CorruptObject(hash.to_string());
+        if depth > MAX_DELTA_DEPTH {
+            return Err(corrupt());
+        }
+        let (base, length) = self.read_entry_header(file, hash, offset)?;
+        let mut content: Vec<u8> = Vec::new();
+        ZlibDecoder::new(Read::by_ref(file).take(length))
+            .read_to_end(&mut content)
+            .map_err(|_| corrupt())?;
+
+        match base {
+            None => Ok(content),
+            Some(base) => {
+                let base_offset = self.find(base.as_str()).ok_or_else(corrupt)?;
+                let base_content = self.read_at(file, base.as_str(), base_offset, depth + 1)?;
+                apply_delta(&base_content, &content).ok_or_else(corrupt)
+            }
+        }
+    }
+}
+
+impl ObjectStore {
+    pub fn pack_folder(&self) -> PathBuf {
+        self.path().join("pack")
+    }
+
+    /// Loads the index of every pack in `.pit/objects/pack`.
+    pub(crate) fn load_packs(&self) -> Result<Vec<PackIndex>> {
+        let mut packs: Vec<PackIndex> = Vec::new();
+        let entries = match fs::read_dir(self.pack_folder()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(packs),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|x| x == "idx") {
+                packs.push(PackIndex::load(&path)?);
+            }
+        }
+
+        Ok(packs)
+    }
+
+    /// Returns the hashes of the objects stored in their own file.
+    pub fn loose_objects(&self) -> Result<Vec<String>> {
+        let mut hashes: Vec<String> = Vec::new();
+        for folder in fs::read_dir(self.path())? {
+            let folder = folder?;
+            let name = folder.file_name().to_string_lossy().to_string();
+            if name.len() != 2
+                || !name.chars().all(|x| x.is_ascii_hexdigit())
+                || !folder.file_type()?.is_dir()
+            {
+                continue;
+            }
+            for file in fs::read_dir(folder.path())? {
+                let file_name = file?.file_name().to_string_lossy().to_string();
+                // skips the temporary files of objects being written.
+                if file_name.len() == 38 && file_name.chars().all(|x| x.is_ascii_hexdigit()) {
+                    hashes.push(name.clone() + file_name.as_str());
+                }
+            }
+        }
+        hashes.sort();
+
+        Ok(hashes)
+    }
+
+    /// Moves every loose object and every existing pack into a single new pack. Blobs are
+    /// stored as deltas against a blob of similar size when that saves at least half of
+    /// their size. Objects are written one at a time, so only the blobs compared for deltas
+    /// are in memory.
+    pub fn repack(&self) -> Result<RepackStats> {
+        let mut stats = RepackStats::default();
+        let mut objects: BTreeMap<String, PackSource> = BTreeMap::new();
+        let loose = self.loose_objects()?;
+        for hash in &loose {
+            stats.size_before += fs::metadata(self.object_path(hash))?.len();
+            objects.insert(hash.clone(), PackSource::Loose);
+        }
+        let packs = self.packs()?;
+        for pack in packs.iter() {
+            stats.size_before += fs::metadata(pack.pack_path())?.len();
+            stats.size_before += fs::metadata(pack.index_path())?.len();
+            for hash in pack.hashes() {
+                objects
+                    .entry(hash.clone())
+                    .or_insert(PackSource::Pack(pack));
+            }
+        }
+        if objects.is_empty() {
+            return Ok(stats);
+        }
+
+        let new_pack = self.build_pack(&objects)?;
+        stats.objects = objects.len();
+        stats.deltas = new_pack.deltas;
+        stats.size_after = new_pack.size()?;
+        stats.pack = Some(new_pack.name.clone());
+        let pack_path = self.install_pack(new_pack)?;
+        for old_pack in packs.iter() {
+            if old_pack.pack_path() != pack_path {
+                self.remove_pack(old_pack)?;
+            }
+        }
+        for hash in &loose {
+            self.remove_loose_object(hash)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Writes a pack holding `objects` to a temporary file. Blobs small enough for deltas
+    /// are written last, smallest first, each one compared with the blobs written just
+    /// before it. Other objects are copied still compressed when they are stored that way.
+    pub(crate) fn build_pack(&self, objects: &BTreeMap<String, PackSource>) -> Result<NewPack> {
+        let names: Vec<&str> = objects.keys().map(|x| x.as_str()).collect();
+        let name = "pack-".to_string() + hash_content(names.concat().as_bytes()).as_str();
+        fs::create_dir_all(self.pack_folder())?;
+        let temporary_path = temporary_path(&self.pack_folder().join(name.clone() + ".pack"));
+        let mut writer = PackWriter::create(&temporary_path, objects.len())?;
+        let written = self.write_pack_objects(&mut writer, objects);
+        let finished = written.and_then(|deltas| Ok((deltas, writer.finish()?)));
+        let (deltas, index) = match finished {
+            Ok(finished) => finished,
+            Err(err) => {
+                let _ = fs::remove_file(&temporary_path);
+                return Err(err);
+            }
+        };
+
+        Ok(NewPack {
+            name,
+            temporary_path,
+            index,
+            deltas,
+        })
+    }
+
+    /// Writes every object and returns the number stored as deltas.
+    fn write_pack_objects(
+        &self,
+        writer: &mut PackWriter,
+        objects: &BTreeMap<String, PackSource>,
+    ) -> Result<usize> {
+        let mut blobs: Vec<(u64, &String, &PackSource)> = Vec::new();
+        for (hash, source) in objects {
+            match self.pack_source_header(hash, source)? {
+                Some((ObjectKind::Blob, size)) if size <= MAX_DELTA_SIZE => {
+                    blobs.push((size, hash, source))
+                }
+                _ => self.copy_to_pack(writer, hash, source)?,
+            }
+        }
+        blobs.sort_by_key(|(size, hash, _)| (*size, *hash));
+
+        let mut deltas = 0;
+        // the last blobs written, with their content and the length of their delta chain.
+        let mut window: VecDeque<(&String, Vec<u8>, usize)> = VecDeque::new();
+        for (_, hash, source) in blobs {
+            let content = match source {
+                PackSource::Loose => self.read_raw(hash)?,
+                PackSource::Pack(pack) => pack
+                    .read(hash)?
+                    .ok_or_else(|| Error::ObjectNotFound(hash.clone()))?,
+            };
+            let mut best: Option<(&String, Vec<u8>, usize)> = None;
+            for (base, base_content, depth) in &window {
+                if *depth >= MAX_DELTA_DEPTH {
+                    continue;
+                }
+                let delta = create_delta(base_content, &content);
+                let is_better = best.as_ref().is_none_or(|(_, x, _)| delta.len() < x.len());
+                if delta.len() < content.len() / 2 && is_better {
+                    best = Some((*base, delta, depth + 1));
+                }
+            }
+            let depth = match best {
+                Some((base, delta, depth)) => {
+                    writer.write_content(hash, Some(base), &delta)?;
+                    deltas += 1;
+                    depth
+                }
+                None => {
+                    writer.write_content(hash, None, &content)?;
+                    0
+                }
+            };
+            window.push_back((hash, content, depth));
+            if window.len() > DELTA_WINDOW {
+                window.pop_front();
+            }
+        }
+
+        Ok(deltas)
+    }
+
+    /// Type and size of an object about to be packed. None for objects written before the
+    /// header existed.
+    fn pack_source_header(
+        &self,
+        hash: &str,
+        source: &PackSource,
+    ) -> Result<Option<(ObjectKind, u64)>> {
+        match source {
+            PackSource::Loose => self.read_header(hash),
+            PackSource::Pack(pack) => pack.read_header(hash),
+        }
+    }
+
+    /// Writes an object whole. Loose objects stored compressed and whole objects of a pack
+    /// are copied without being decompressed.
+    fn copy_to_pack(&self, writer: &mut PackWriter, hash: &str, source: &PackSource) -> Result<()> {
+        match source {
+            PackSource::Loose if self.is_compressed(hash)? => {
+                let file = File::open(self.object_path(hash))?;
+                let length = file.metadata()?.len();
+                writer.write_entry(hash, None, length, file)
+            }
+            PackSource::Loose => writer.write_content(hash, None, &self.read_raw(hash)?),
+            PackSource::Pack(pack) => match pack.open_whole(hash)? {
+                Some((file, length)) => writer.write_entry(hash, None, length, file),
+                None => {
+                    let content = pack
+                        .read(hash)?
+                        .ok_or_else(|| Error::ObjectNotFound(hash.to_string()))?;
+                    writer.write_content(hash, None, &content)
+                }
+            },
+        }
+    }
+
+    /// Moves a pack built by `build_pack` into `.pit/objects/pack` and returns its path.
+    pub(crate) fn install_pack(&self, pack: NewPack) -> Result<PathBuf> {
+        let pack_path = self.pack_folder().join(pack.name.clone() + ".pack");
+        let index_path = self.pack_folder().join(pack.name.clone() + ".idx");
+        // the index is written last so readers never find an index without its pack.
+        fs::rename(&pack.temporary_path, &pack_path)?;
+        write_file(&index_path, &pack.index)?;
+        self.reload_packs();
+
+        Ok(pack_path)
+    }
+
+    /// Deletes a pack built by `build_pack` without installing it.
+    pub(crate) fn discard_pack(&self, pack: NewPack) -> Result<()> {
+        Ok(fs::remove_file(pack.temporary_path)?)
+    }
+
+    pub(crate) fn remove_pack(&self, pack: &PackIndex) -> Result<()> {
+        fs::remove_file(pack.index_path())?;
+        fs::remove_file(pack.pack_path())?;
+        self.reload_packs();
+
+        Ok(())
+    }
+
+    pub(crate) fn remove_loose_object(&self, hash: &str) -> Result<()> {
+        let object_path = self.object_path(hash);
+        fs::remove_file(&object_path)?;
+        if let Some(folder) = object_path.parent() {
+            // only succeeds once the folder is empty.
+            let _ = fs::remove_dir(folder);
+        }
+
+        Ok(())
+    }
+}
+
+/// Where an object being packed is read from.
+pub(crate) enum PackSource<'a> {
+    Loose,
+    Pack(&'a PackIndex),
+}
+
+/// A pack written to a temporary file by `ObjectStore::build_pack`, with its index.
+pub(crate) struct NewPack {
+    pub name: String,
+    pub deltas: usize,
+    temporary_path: PathBuf,
+    index: Vec<u8>,
+}
+
+impl NewPack {
+    pub fn size(&self) -> Result<u64> {
+        Ok(fs::metadata(&self.temporary_path)?.len() + self.index.len() as u64)
+    }
+}
+
+/// Appends the objects of a pack to its file one at a time and builds the index.
+struct PackWriter {
+    pack: BufWriter<File>,
+    /// Hash and offset of each object written.
+    entries: Vec<(String, u64)>,
+    offset: u64,
+}
+
+impl PackWriter {
+    fn create(path: &Path, count: usize) -> Result<Self> {
+        let mut pack = BufWriter::new(File::create(path)?);
+        pack.write_all(PACK_SIGNATURE)?;
+        pack.write_all(&PACK_VERSION.to_be_bytes())?;
+        pack.write_all(&(count as u32).to_be_bytes())?;
+
+        Ok(PackWriter {
+            pack,
+            entries: Vec::with_capacity(count),
+            offset: 12,
+        })
+    }
+
+    /// Writes an object whose `length` bytes of zlib compressed data are read from `data`.
+    fn write_entry(
+        &mut self,
+        hash: &str,
+        base: Option<&str>,
+        length: u64,
+        data: impl Read,
+    ) -> Result<()> {
+        let mut header: Vec<u8> = vec![if base.is_some() { DELTA } else { WHOLE }];
+        if let Some(base) = base {
+            header.extend(decode_hash(base));
+        }
+        header.extend(length.to_be_bytes());
+        self.pack.write_all(&header)?;
+        if io::copy(&mut data.take(length), &mut self.pack)? != length {
+            return Err(Error::CorruptObject(hash.to_string()));
+        }
+        self.entries.push((hash.to_string(), self.offset));
+        self.offset += header.len() as u64 + length;
+
+        Ok(())
+    }
+
+    fn write_content(&mut self, hash: &str, base: Option<&str>, content: &[u8]) -> Result<()> {
+        let compressed = compress(content)?;
+        self.write_entry(hash, base, compressed.len() as u64, compressed.as_slice())
+    }
+
+    /// Flushes the pack and returns its index.
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        self.pack.flush()?;
+        self.entries.sort();
+        let mut index: Vec<u8> = INDEX_SIGNATURE.to_vec();
+        index.extend(PACK_VERSION.to_be_bytes());
+        index.extend((self.entries.len() as u32).to_be_bytes());
+        for (hash, offset) in &self.entries {
+            index.extend(decode_hash(hash));
+            index.extend(offset.to_be_bytes());
+        }
+
+        Ok(index)
+    }
+}
+
+pub(crate) fn read_u32(content: &[u8], start: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        content.get(start..start + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u64(content: &[u8], start: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        content.get(start..start + 8)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Blob, Object};
+    use crate::testing::TemporaryFolder;
+
+    /// An object store in a temporary folder.
+    fn temporary_store(name: &str) -> (TemporaryFolder, ObjectStore) {
+        let folder = TemporaryFolder::new("pack", name);
+        let store = ObjectStore::new(folder.path().to_path_buf());
+        (folder, store)
+    }
+
+    fn blob(content: Vec<u8>) -> Object {
+        Object::Blob(Blob { content })
+    }
+
+    #[test]
+    fn reads_objects_back_from_a_pack() {
+        let (_folder, store) = temporary_store("read");
+        let base: Vec<u8> = (0..2000u32)
+            .flat_map(|x| x.to_string().into_bytes())
+            .collect();
+        let mut objects: Vec<Object> = vec![blob(b"small".to_vec()), blob(Vec::new())];
+        for version in 0..5u8 {
+            let mut content = base.clone();
+            content.extend(vec![version; 100 * version as usize]);
+            objects.push(blob(content));
+        }
+        let hashes: Vec<String> = objects.iter().map(|x| store.write(x).unwrap()).collect();
+
+        let stats = store.repack().unwrap();
+
+        assert_eq!(stats.objects, objects.len());
+        assert!(stats.deltas > 0);
+        assert!(stats.size_after < stats.size_before);
+        assert!(store.loose_objects().unwrap().is_empty());
+        for (hash, object) in hashes.iter().zip(&objects) {
+            assert_eq!(&store.read(hash).unwrap(), object);
+        }
+    }
+
+    #[test]
+    fn repacks_existing_packs_with_new_objects() {
+        let (_folder, store) = temporary_store("repack");
+        let first = store.write(&blob(b"first".to_vec())).unwrap();
+        store.repack().unwrap();
+        let second = store.write(&blob(b"second".to_vec())).unwrap();
+
+        let stats = store.repack().unwrap();
+
+        assert_eq!(stats.objects, 2);
+        assert_eq!(store.packs().unwrap().len(), 1);
+        assert_eq!(store.read(&first).unwrap(), blob(b"first".to_vec()));
+        assert_eq!(store.read(&second).unwrap(), blob(b"second".to_vec()));
+    }
+
+    #[test]
+    fn copies_large_blobs_without_deltas() {
+        let (_folder, store) = temporary_store("large");
+        let base = vec![b'a'; MAX_DELTA_SIZE as usize + 1];
+        let mut changed = base.clone();
+        changed[0] ^= 1;
+        let hashes: Vec<String> = [&base, &changed]
+            .iter()
+            .map(|x| store.write(&blob(x.to_vec())).unwrap())
+            .collect();
+
+        let stats = store.repack().unwrap();
+        // a second repack copies the whole entries of the first pack.
+        store.repack().unwrap();
+
+        assert_eq!(stats.deltas, 0);
+        let header = store.read_header(&hashes[0]).unwrap();
+        assert_eq!(header, Some((ObjectKind::Blob, base.len() as u64)));
+        assert_eq!(store.read(&hashes[0]).unwrap(), blob(base));
+        assert_eq!(store.read(&hashes[1]).unwrap(), blob(changed));
+    }
+
+    #[test]
+    fn rejects_a_damaged_pack_index() {
+        let (_folder, store) = temporary_store("damaged");
+        store.write(&blob(b"content".to_vec())).unwrap();
+        store.repack().unwrap();
+        let index_path = store.packs().unwrap()[0].index_path();
+        let content = fs::read(&index_path).unwrap();
+        fs::write(&index_path, &content[..content.len() - 3]).unwrap();
+
+        assert!(PackIndex::load(&index_path).is_err());
+    }
+}
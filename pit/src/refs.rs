@@ -0,0 +1,29 @@
+/// A branch. HEAD stores the ref path (`refs/<name>`) of the current branch and the ref file
+/// stores the hash of the branch's last commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ref {
+    name: String,
+}
+
+impl Ref {
+    pub fn branch(name: &str) -> Self {
+        Ref {
+            name: name.to_string(),
+        }
+    }
+
+    /// Parses the content of the HEAD file.
+    pub fn from_head(content: &str) -> Self {
+        let content = content.trim();
+        Ref::branch(content.strip_prefix("refs/").unwrap_or(content))
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Path of the ref file relative to the `.pit` folder.
+    pub fn path(&self) -> String {
+        "refs/".to_string() + self.name.as_str()
+    }
+}
@@ -0,0 +1,346 @@
+use crate::error::Result;
+use crate::object::is_binary;
+use crate::repository::{FileEntry, Repository};
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Files at least this similar (in percent) to a removed file are reported as renamed.
+pub const DEFAULT_RENAME_THRESHOLD: u8 = 50;
+/// Above this many (new file, old file) pairs only identical files are matched, comparing
+/// the content of every pair would take too long.
+const MAX_SIMILARITY_PAIRS: usize = 1_000_000;
+/// Larger files are only matched when identical: comparing their lines would read them
+/// whole, which `pit diff` does not do either.
+const MAX_SIMILARITY_SIZE: u64 = 10 * 1024 * 1024;
+/// Empty files are all the same blob, but one is not a rename of another.
+const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+/// A new file that has the content, or most of it, of an old file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    /// Share of the content kept, in percent.
+    pub similarity: u8,
+    /// The old file still exists, the new one is a copy of it.
+    pub copy: bool,
+}
+
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.copy { "copied" } else { "renamed" };
+        write!(
+            f,
+            "{}: {} -> {} ({}%)",
+            kind, self.from, self.to, self.similarity
+        )
+    }
+}
+
+/// How the files of a snapshot differ from the files of an older one.
+#[derive(Debug, Default)]
+pub struct FileChanges {
+    pub added: Vec<String>,
+    /// Files at the same path with another content or mode.
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    pub renamed: Vec<Rename>,
+}
+
+impl FileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.modified.is_empty()
+            && self.deleted.is_empty()
+            && self.renamed.is_empty()
+    }
+}
+
+impl Repository {
+    /// Compares two sets of files, reporting the new files that come from an old one as
+    /// renamed or copied instead of added.
+    pub fn compare_files(
+        &self,
+        old_files: &BTreeMap<String, FileEntry>,
+        new_files: &BTreeMap<String, FileEntry>,
+        threshold: u8,
+    ) -> Result<FileChanges> {
+        let renamed = self.detect_renames(old_files, new_files, threshold)?;
+        let mut changes = FileChanges::default();
+        for (path, file) in new_files {
+            match old_files.get(path) {
+                Some(old_file) if old_file != file => changes.modified.push(path.clone()),
+                Some(_) => {}
+                None if renamed.iter().any(|x| x.to == *path) => {}
+                None => changes.added.push(path.clone()),
+            }
+        }
+        for path in old_files.keys() {
+            let moved = renamed.iter().any(|x| !x.copy && x.from == *path);
+            if !new_files.contains_key(path) && !moved {
+                changes.deleted.push(path.clone());
+            }
+        }
+        changes.renamed = renamed;
+
+        Ok(changes)
+    }
+
+    /// Finds the files of `new_files` that are not in `old_files` but have the content of
+    /// one of its removed or modified files, as git does without `--find-copies-harder`:
+    /// unchanged files are not read. Files with the same blob are matched first, then the
+    /// most similar pairs at least `threshold` percent alike. Each removed file is renamed
+    /// at most once; new files matching a file that still exists, or one already renamed,
+    /// are copies.
+    pub fn detect_renames(
+        &self,
+        old_files: &BTreeMap<String, FileEntry>,
+        new_files: &BTreeMap<String, FileEntry>,
+        threshold: u8,
+    ) -> Result<Vec<Rename>> {
+        let mut targets: Vec<(&String, &FileEntry)> = new_files
+            .iter()
+            .filter(|(path, file)| !old_files.contains_key(*path) && file.hash != EMPTY_BLOB)
+            .collect();
+        if targets.is_empty() {
+            return Ok(Vec::new());
+        }
+        // removed files come first so an identical blob is a rename rather than a copy.
+        let mut sources: Vec<(&String, &FileEntry, bool)> = old_files
+            .iter()
+            .filter(|(path, file)| new_files.get(*path) != Some(*file))
+            .map(|(path, file)| (path, file, new_files.contains_key(path)))
+            .collect();
+        sources.sort_by_key(|(_, _, kept)| *kept);
+
+        let mut renames: Vec<Rename> = Vec::new();
+        let mut add = |from: &String, to: &String, similarity: u8, kept: bool| {
+            let copy = kept || renames.iter().any(|x| !x.copy && x.from == *from);
+            renames.push(Rename {
+                from: from.clone(),
+                to: to.clone(),
+                similarity,
+                copy,
+            });
+        };
+
+        targets.retain(|(path, file)| {
+            let source = sources.iter().find(|(_, x, _)| x.hash == file.hash);
+            match source {
+                Some((from, _, kept)) => add(from, path, 100, *kept),
+                None => return true,
+            }
+            false
+        });
+
+        if !targets.is_empty() && targets.len() * sources.len() <= MAX_SIMILARITY_PAIRS {
+            let mut blobs = BlobCache::new(self);
+            let mut pairs: Vec<(u8, usize, usize)> = Vec::new();
+            for (target_index, (_, target)) in targets.iter().enumerate() {
+                for (source_index, (_, source, _)) in sources.iter().enumerate() {
+                    if let Some(similarity) = blobs.similarity(source, target, threshold)? {
+                        pairs.push((similarity, target_index, source_index));
+                    }
+                }
+            }
+            // the most similar pairs first, removed files before the ones still there.
+            pairs.sort_by_key(|(similarity, target, source)| {
+                (u8::MAX - similarity, sources[*source].2, *target, *source)
+            });
+            let mut matched: Vec<bool> = vec![false; targets.len()];
+            for (similarity, target_index, source_index) in pairs {
+                if matched[target_index] {
+                    continue;
+                }
+                matched[target_index] = true;
+                let (from, _, kept) = sources[source_index];
+                add(from, targets[target_index].0, similarity, kept);
+            }
+        }
+        renames.sort_by(|a, b| a.to.cmp(&b.to));
+
+        Ok(renames)
+    }
+}
+
+/// Blob contents and sizes read while comparing files, so each blob is read once.
+struct BlobCache<'a> {
+    repository: &'a Repository,
+    sizes: HashMap<String, u64>,
+    contents: HashMap<String, Vec<u8>>,
+}
+
+impl<'a> BlobCache<'a> {
+    fn new(repository: &'a Repository) -> Self {
+        BlobCache {
+            repository,
+            sizes: HashMap::new(),
+            contents: HashMap::new(),
+        }
+    }
+
+    fn size(&mut self, hash: &str) -> Result<u64> {
+        if let Some(size) = self.sizes.get(hash) {
+            return Ok(*size);
+        }
+        let size = self.repository.blob_size(hash)?;
+        self.sizes.insert(hash.to_string(), size);
+
+        Ok(size)
+    }
+
+    fn load(&mut self, hash: &str) -> Result<()> {
+        if !self.contents.contains_key(hash) {
+            let content = self.repository.read_blob_content(hash)?;
+            self.contents.insert(hash.to_string(), content);
+        }
+
+        Ok(())
+    }
+
+    /// Returns how similar two files are when they are at least `threshold` percent alike.
+    /// Empty, binary and large files are never similar to another file.
+    fn similarity(
+        &mut self,
+        old: &FileEntry,
+        new: &FileEntry,
+        threshold: u8,
+    ) -> Result<Option<u8>> {
+        let (old_size, new_size) = (self.size(&old.hash)?, self.size(&new.hash)?);
+        let (smaller, larger) = (old_size.min(new_size), old_size.max(new_size));
+        // the shared content cannot be larger than the smaller file.
+        if smaller == 0 || smaller * 100 < larger * threshold as u64 {
+            return Ok(None);
+        }
+        if larger > MAX_SIMILARITY_SIZE {
+            return Ok(None);
+        }
+        self.load(&old.hash)?;
+        self.load(&new.hash)?;
+        let (old_content, new_content) = (&self.contents[&old.hash], &self.contents[&new.hash]);
+        if is_binary(old_content) || is_binary(new_content) {
+            return Ok(None);
+        }
+        let similarity = line_similarity(old_content, new_content);
+
+        Ok((similarity >= threshold).then_some(similarity))
+    }
+}
+
+/// The bytes of the lines both contents share, in percent of the larger one.
+fn line_similarity(old: &[u8], new: &[u8]) -> u8 {
+    let old_lines: Vec<&[u8]> = old.split_inclusive(|x| *x == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new.split_inclusive(|x| *x == b'\n').collect();
+    let mut shared: usize = 0;
+    for op in capture_diff_slices(Algorithm::Myers, &old_lines, &new_lines) {
+        if let DiffOp::Equal { old_index, len, .. } = op {
+            shared += old_lines[old_index..old_index + len]
+                .iter()
+                .map(|x| x.len())
+                .sum::<usize>();
+        }
+    }
+
+    (shared * 100 / old.len().max(new.len())) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TemporaryRepository;
+
+    fn files(repository: &Repository, contents: &[(&str, &str)]) -> BTreeMap<String, FileEntry> {
+        contents
+            .iter()
+            .map(|(path, content)| {
+                let hash = repository.write_blob(content.as_bytes().to_vec()).unwrap();
+                (path.to_string(), FileEntry::regular(&hash))
+            })
+            .collect()
+    }
+
+    fn lines(first: usize, last: usize) -> String {
+        (first..last).map(|x| format!("line {}\n", x)).collect()
+    }
+
+    fn rename(from: &str, to: &str, similarity: u8, copy: bool) -> Rename {
+        Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+            similarity,
+            copy,
+        }
+    }
+
+    #[test]
+    fn matches_identical_files() {
+        let repository = TemporaryRepository::new("rename", "identical");
+        let content = lines(0, 20);
+        let old_files = files(&repository, &[("a", &content), ("kept", "kept")]);
+        let new_files = files(&repository, &[("b", &content), ("kept", "kept")]);
+
+        let renames = repository
+            .detect_renames(&old_files, &new_files, 50)
+            .unwrap();
+
+        assert_eq!(renames, [rename("a", "b", 100, false)]);
+    }
+
+    #[test]
+    fn matches_similar_files_above_the_threshold() {
+        let repository = TemporaryRepository::new("rename", "similar");
+        let old_files = files(&repository, &[("a", &lines(10, 30))]);
+        let new_files = files(&repository, &[("b", &(lines(10, 20) + &lines(30, 40)))]);
+
+        let renames = repository
+            .detect_renames(&old_files, &new_files, 50)
+            .unwrap();
+        assert_eq!(renames, [rename("a", "b", 50, false)]);
+        let renames = repository
+            .detect_renames(&old_files, &new_files, 51)
+            .unwrap();
+        assert!(renames.is_empty());
+    }
+
+    #[test]
+    fn reports_copies_of_modified_files() {
+        let repository = TemporaryRepository::new("rename", "copy");
+        let content = lines(0, 20);
+        let old_files = files(&repository, &[("a", &content), ("same", &lines(50, 70))]);
+        let new_files = files(
+            &repository,
+            &[
+                ("a", &(content.clone() + "more\n")),
+                ("b", &content),
+                ("c", &lines(50, 70)),
+                ("same", &lines(50, 70)),
+            ],
+        );
+
+        let renames = repository
+            .detect_renames(&old_files, &new_files, 50)
+            .unwrap();
+
+        // unchanged files are not looked at as the source of a copy.
+        assert_eq!(renames, [rename("a", "b", 100, true)]);
+    }
+
+    #[test]
+    fn only_matches_large_files_when_identical() {
+        let repository = TemporaryRepository::new("rename", "large");
+        let content = "a".repeat(MAX_SIMILARITY_SIZE as usize);
+        let old_files = files(&repository, &[("a", &content)]);
+        let new_files = files(&repository, &[("b", &(content.clone() + "b"))]);
+        let identical = files(&repository, &[("b", &content)]);
+
+        let renames = repository
+            .detect_renames(&old_files, &new_files, 50)
+            .unwrap();
+        assert!(renames.is_empty());
+        let renames = repository
+            .detect_renames(&old_files, &identical, 50)
+            .unwrap();
+        assert_eq!(renames, [rename("a", "b", 100, false)]);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::error::{Error, Result};
+use crate::index::{Index, INDEX_FILE};
+use crate::object::{Blob, Commit, FileMode, Object, ObjectKind, Tree, TreeEntry};
+use crate::object_store::ObjectStore;
+use crate::refs::Ref;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const PIT_FOLDER: &str = ".pit";
+/// Version of the repository format written by this pit, stored in `.pit/format`.
+/// Repositories without the file use format 0, where blobs also stored their path. Format 1
+/// kept every object directly in `.pit/objects`. Format 2 staged files in the `.pit/staging`
+/// text file instead of the index.
+pub const FORMAT_VERSION: u32 = 3;
+pub const FORMAT_FILE: &str = "format";
+
+/// A file of a commit or of the index: its blob and its mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub hash: String,
+    pub mode: FileMode,
+}
+
+impl FileEntry {
+    pub fn new(hash: &str, mode: FileMode) -> Self {
+        FileEntry {
+            hash: hash.to_string(),
+            mode,
+        }
+    }
+
+    pub fn regular(hash: &str) -> Self {
+        FileEntry::new(hash, FileMode::Regular)
+    }
+}
+
+/// A working directory tracked by pit, with its `.pit` folder.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    root: PathBuf,
+    objects: ObjectStore,
+}
+
+impl Repository {
+    pub(crate) fn new(root: &Path) -> Self {
+        Repository {
+            root: root.to_path_buf(),
+            objects: ObjectStore::new(root.join(PIT_FOLDER).join("objects")),
+        }
+    }
+
+    /// Creates the `.pit` folder with an empty `main` branch.
+    pub fn init(root: &Path) -> Result<Repository> {
+        if !root.is_dir() {
+            return Err(Error::NotADirectory(root.display().to_string()));
+        }
+        let pit_path = root.join(PIT_FOLDER);
+        if pit_path.exists() {
+            return Err(Error::AlreadyInitialized);
+        }
+
+        fs::create_dir(&pit_path)?;
+        fs::create_dir(pit_path.join("objects"))?;
+        Index::new(pit_path.join(INDEX_FILE)).save()?;
+        fs::create_dir(pit_path.join("refs"))?;
+        fs::write(pit_path.join("HEAD"), Ref::branch("main").path())?;
+        fs::write(pit_path.join(FORMAT_FILE), FORMAT_VERSION.to_string())?;
+
+        Ok(Repository::new(root))
+    }
+
+    /// Opens the repository at `root`. Repositories in an older format must be migrated
+    /// first with `Repository::migrate`.
+    pub fn open(root: &Path) -> Result<Repository> {
+        if !root.join(PIT_FOLDER).is_dir() {
+            return Err(Error::NotARepository);
+        }
+        let version = read_format_version(root)?;
+        if version < FORMAT_VERSION {
+            return Err(Error::OldFormat(version));
+        }
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat(version.to_string()));
+        }
+
+        Ok(Repository::new(root))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn pit_path(&self) -> PathBuf {
+        self.root.join(PIT_FOLDER)
+    }
+
+    pub fn objects(&self) -> &ObjectStore {
+        &self.objects
+    }
+
+    /// Location on disk of a path relative to the working directory.
+    pub fn work_path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn head(&self) -> Result<Ref> {
+        let head = fs::read_to_string(self.pit_path().join("HEAD"))?;
+
+        Ok(Ref::from_head(head.as_str()))
+    }
+
+    pub fn set_head(&self, branch: &Ref) -> Result<()> {
+        fs::write(self.pit_path().join("HEAD"), branch.path())?;
+
+        Ok(())
+    }
+
+    pub fn ref_exists(&self, branch: &Ref) -> bool {
+        self.pit_path().join(branch.path()).is_file()
+    }
+
+    /// Returns the last commit of the branch, None if the branch has no commits yet.
+    pub fn read_ref(&self, branch: &Ref) -> Result<Option<String>> {
+        match fs::read_to_string(self.pit_path().join(branch.path())) {
+            Ok(commit) if commit.trim().is_empty() => Ok(None),
+            Ok(commit) => Ok(Some(commit.trim().to_string())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    pub fn write_ref(&self, branch: &Ref, commit: &str) -> Result<()> {
+        fs::write(self.pit_path().join(branch.path()), commit)?;
+
+        Ok(())
+    }
+
+    /// Returns every branch, sorted by name.
+    pub fn branches(&self) -> Result<Vec<Ref>> {
+        let mut branches: Vec<Ref> = Vec::new();
+        for entry in fs::read_dir(self.pit_path().join("refs"))? {
+            branches.push(Ref::branch(entry?.file_name().to_string_lossy().as_ref()));
+        }
+        branches.sort_by(|x, y| x.name().cmp(y.name()));
+
+        Ok(branches)
+    }
+
+    pub fn head_commit(&self) -> Result<Option<String>> {
+        self.read_ref(&self.head()?)
+    }
+
+    /// Resolves a branch name or a commit hash to a commit hash.
+    pub fn resolve_revision(&self, revision: &str) -> Result<String> {
+        let revision = revision.trim();
+        let branch = Ref::branch(revision);
+        if self.ref_exists(&branch) {
+            return self
+                .read_ref(&branch)?
+                .ok_or_else(|| Error::RevisionNotFound(revision.to_string()));
+        }
+        if self.objects.contains(revision) {
+            return Ok(revision.to_string());
+        }
+
+        Err(Error::RevisionNotFound(revision.to_string()))
+    }
+
+    pub fn read_blob_content(&self, hash: &str) -> Result<Vec<u8>> {
+        Ok(self.objects.read_blob(hash)?.content)
+    }
+
+    /// Size of the content of a blob, read from the object header when it has one.
+    pub fn blob_size(&self, hash: &str) -> Result<u64> {
+        match self.objects.read_header(hash)? {
+            Some((_, size)) => Ok(size),
+            None => Ok(self.read_blob_content(hash)?.len() as u64),
+        }
+    }
+
+    pub fn write_blob(&self, content: Vec<u8>) -> Result<String> {
+        self.objects.write(&Object::Blob(Blob { content }))
+    }
+
+    /// Walks the tree objects of a commit and returns every file as path -> blob and mode.
+    pub fn read_commit_files(&self, commit: Option<&str>) -> Result<BTreeMap<String, FileEntry>> {
+        match commit {
+            None => Ok(BTreeMap::new()),
+            Some(commit) => {
+                let tree = self.objects.read_commit(commit)?.tree;
+                self.read_tree_files(tree.as_str())
+            }
+        }
+    }
+
+    pub fn read_tree_files(&self, tree: &str) -> Result<BTreeMap<String, FileEntry>> {
+        let mut files: BTreeMap<String, FileEntry> = BTreeMap::new();
+        let mut queue: VecDeque<(String, String)> = VecDeque::new();
+        queue.push_back((".".to_string(), tree.to_string()));
+        while let Some((path, hash)) = queue.pop_front() {
+            for entry in self.objects.read_tree(hash.as_str())?.entries {
+                let entry_path =
+                    normalize_path((path.clone() + "/" + entry.name.as_str()).as_str());
+                if entry_path == "." || is_pit_path(entry_path.as_str()) {
+                    continue;
+                }
+                match entry.kind() {
+                    ObjectKind::Tree => queue.push_back((entry_path, entry.hash)),
+                    ObjectKind::Blob => {
+                        files.insert(entry_path, FileEntry::new(&entry.hash, entry.mode));
+                    }
+                    ObjectKind::Commit => {}
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Writes the tree objects for a flat list of files (path -> blob and mode) and returns
+    /// the root tree hash.
+    pub fn write_tree(&self, files: &BTreeMap<String, FileEntry>) -> Result<String> {
+        self.write_tree_level(".", files)
+    }
+
+    fn write_tree_level(&self, path: &str, files: &BTreeMap<String, FileEntry>) -> Result<String> {
+        let prefix = path.to_string() + "/";
+        let mut tree = Tree {
+            entries: Vec::new(),
+        };
+        let mut last_folder: String = Default::default();
+        let mut names: HashSet<&str> = HashSet::new();
+        for (file, entry) in files.range(prefix.clone()..) {
+            if !file.starts_with(prefix.as_str()) {
+                break;
+            }
+            match file[prefix.len()..].split_once('/') {
+                None => {
+                    let name = &file[prefix.len()..];
+                    if !names.insert(name) {
+                        return Err(Error::FileAndFolder(file.clone()));
+                    }
+                    tree.entries.push(TreeEntry {
+                        mode: entry.mode,
+                        hash: entry.hash.clone(),
+                        name: name.to_string(),
+                    });
+                }
+                Some((folder, _)) => {
+                    let folder_path = prefix.clone() + folder;
+                    if folder_path == last_folder {
+                        continue;
+                    }
+                    // the files of a folder come one after the other, a file sorts apart.
+                    if !names.insert(folder) {
+                        return Err(Error::FileAndFolder(folder_path));
+                    }
+                    last_folder = folder_path.clone();
+                    tree.entries.push(TreeEntry {
+                        mode: FileMode::Tree,
+                        hash: self.write_tree_level(folder_path.as_str(), files)?,
+                        name: folder.to_string(),
+                    });
+                }
+            }
+        }
+
+        self.objects.write(&Object::Tree(tree))
+    }
+
+    pub fn write_commit(&self, tree: &str, parents: Vec<String>, message: &str) -> Result<String> {
+        self.objects.write(&Object::Commit(Commit {
+            tree: tree.to_string(),
+            parents,
+            message: message.to_string(),
+        }))
+    }
+
+    /// Creates a commit from the last commit of the current branch, the staged files and the
+    /// staged removals. Returns None when there is nothing to commit.
+    pub fn commit(&self, message: &str) -> Result<Option<String>> {
+        if self.is_merging() {
+            return Err(Error::MergeInProgress);
+        }
+        let head = self.head()?;
+        let parent = self.read_ref(&head)?;
+        let staged = self.staged_files()?;
+
+        let mut files = self.read_commit_files(parent.as_deref())?;
+        let mut changed = false;
+        for (path, entry) in staged {
+            if files.get(&path) != Some(&entry) {
+                files.insert(path, entry);
+                changed = true;
+            }
+        }
+        // folders left without files get no tree.
+        for path in self.removed_files()? {
+            changed |= files.remove(&path).is_some();
+        }
+        if !changed {
+            return Ok(None);
+        }
+
+        let tree = self.write_tree(&files)?;
+        let commit = self.write_commit(tree.as_str(), parent.into_iter().collect(), message)?;
+        self.write_ref(&head, commit.as_str())?;
+        self.clear_staging()?;
+
+        Ok(Some(commit))
+    }
+}
+
+/// Reads `.pit/format`, returning 0 for repositories created before the file existed.
+pub fn read_format_version(root: &Path) -> Result<u32> {
+    match fs::read_to_string(root.join(PIT_FOLDER).join(FORMAT_FILE)) {
+        Ok(version) => version
+            .trim()
+            .parse()
+            .map_err(|_| Error::UnsupportedFormat(version.trim().to_string())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(Error::Io(err)),
+    }
+}
+
+/// Turns any spelling of a working directory path (`a.txt`, `././a.txt`) into `./a.txt`.
+pub fn normalize_path(path: &str) -> String {
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect();
+    if components.is_empty() {
+        return ".".to_string();
+    }
+
+    "./".to_string() + components.join("/").as_str()
+}
+
+pub fn is_pit_path(path: &str) -> bool {
+    path.split('/').any(|x| x == PIT_FOLDER)
+}
+
+/// Writes through a temporary file so a crash never leaves half a file behind. Each write
+/// uses its own temporary file, so threads writing the same object do not collide.
+pub(crate) fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let temporary_path = temporary_path(path);
+    fs::write(&temporary_path, content)?;
+    fs::rename(&temporary_path, path)?;
+
+    Ok(())
+}
+
+/// A temporary file next to `path` that no other write uses.
+pub(crate) fn temporary_path(path: &Path) -> PathBuf {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let mut temporary_name = path.file_name().unwrap_or_default().to_os_string();
+    temporary_name.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    path.with_file_name(temporary_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TemporaryRepository;
+
+    #[test]
+    fn refuses_a_path_that_is_a_file_and_a_folder() {
+        let repository = TemporaryRepository::new("repository", "file-and-folder");
+        let blob = repository.write_blob(b"content".to_vec()).unwrap();
+        let files: BTreeMap<String, FileEntry> = ["./a", "./a b", "./a/x"]
+            .iter()
+            .map(|x| (x.to_string(), FileEntry::regular(&blob)))
+            .collect();
+
+        let tree = repository.write_tree(&files);
+
+        assert!(matches!(tree, Err(Error::FileAndFolder(path)) if path == "./a"));
+    }
+}
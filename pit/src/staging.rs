@@ -0,0 +1,454 @@
+use crate::error::{Error, Result};
+use crate::ignore::read_ignored_files;
+use crate::index::{Index, IndexEntry, INDEX_FILE};
+use crate::repository::{normalize_path, FileEntry, Repository, PIT_FOLDER};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+#[derive(Debug, Default)]
+pub struct AddResult {
+    pub added: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct RemoveResult {
+    pub removed: Vec<String>,
+    /// Paths that match no tracked file.
+    pub not_tracked: Vec<String>,
+}
+
+impl Repository {
+    pub fn index_path(&self) -> PathBuf {
+        self.pit_path().join(INDEX_FILE)
+    }
+
+    pub fn index(&self) -> Result<Index> {
+        Index::load(self.index_path())
+    }
+
+    /// Returns the staged files as path -> blob and mode.
+    pub fn staged_files(&self) -> Result<BTreeMap<String, FileEntry>> {
+        Ok(self
+            .index()?
+            .staged()
+            .map(|(path, entry)| (path.clone(), entry.file()))
+            .collect())
+    }
+
+    /// Returns the committed files the next commit removes.
+    pub fn removed_files(&self) -> Result<BTreeSet<String>> {
+        Ok(self.index()?.removed().cloned().collect())
+    }
+
+    pub fn clear_staging(&self) -> Result<()> {
+        let mut index = self.index()?;
+        index.unstage_all();
+        index.save()
+    }
+
+    /// Adds the entries of already stored blobs to the index, replacing the staged version
+    /// of the same path. A path is a file or a folder, never both: the tracked files below
+    /// a staged file, and the tracked files at a folder of its path, are no longer tracked.
+    pub fn stage_files(&self, files: &BTreeMap<String, IndexEntry>) -> Result<()> {
+        let committed = self.read_commit_files(self.head_commit()?.as_deref())?;
+        let mut index = self.index()?;
+        for (path, entry) in files {
+            index.insert(path, entry.clone());
+        }
+        let tracked = tracked_files(&committed, &index);
+        for path in files.keys() {
+            let prefix = path.clone() + "/";
+            let below = tracked
+                .range(prefix.clone()..)
+                .take_while(|(x, _)| x.starts_with(prefix.as_str()))
+                .map(|(x, _)| x);
+            let above = path
+                .match_indices('/')
+                .filter_map(|(end, _)| tracked.get_key_value(&path[..end]).map(|(x, _)| x));
+            for replaced in below.chain(above) {
+                match committed.get(replaced) {
+                    Some(file) => index.insert(replaced, IndexEntry::removal(file)),
+                    None => index.remove(replaced),
+                }
+            }
+        }
+
+        index.save()
+    }
+
+    /// Builds the index entry of a working file stored as `file`.
+    pub fn index_entry(&self, path: &str, file: &FileEntry) -> Result<IndexEntry> {
+        match fs::symlink_metadata(self.work_path(path)) {
+            Ok(metadata) => Ok(IndexEntry::new(&file.hash, &metadata)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(IndexEntry::without_stat(file)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Adds files and folders (recursively) to the index, skipping the paths listed
+    /// in `.pitignore` files. The files are listed first, then hashed and stored on every
+    /// core, and the index is written once at the end.
+    pub fn add(&self, paths: &[String]) -> Result<AddResult> {
+        let ignored = read_ignored_files(self.root())?;
+        let mut result = AddResult::default();
+        let mut files: BTreeSet<String> = BTreeSet::new();
+
+        for path in paths {
+            let path = normalize_path(path);
+            let file_path = self.work_path(path.as_str());
+            // a dangling symbolic link is still added.
+            if fs::symlink_metadata(&file_path).is_err() {
+                result.missing.push(path);
+                continue;
+            }
+            self.collect_files(path.as_str(), &ignored, &mut files)?;
+        }
+
+        let entries = files
+            .par_iter()
+            .map(|path| Ok((path.clone(), self.store_file(path)?)))
+            .collect::<Result<BTreeMap<String, IndexEntry>>>()?;
+        self.stage_files(&entries)?;
+        result.added = files.into_iter().collect();
+
+        Ok(result)
+    }
+
+    /// Stages the removal of tracked files and folders (recursively) and deletes them from
+    /// the working directory, or keeps them there as untracked files with `cached`. Files
+    /// whose working or staged version is not committed are only deleted with `force`.
+    /// Files that were only staged are simply unstaged.
+    pub fn remove(&self, paths: &[String], cached: bool, force: bool) -> Result<RemoveResult> {
+        let committed = self.read_commit_files(self.head_commit()?.as_deref())?;
+        let mut index = self.index()?;
+        let tracked = tracked_files(&committed, &index);
+
+        let mut result = RemoveResult::default();
+        let mut files: BTreeMap<String, FileEntry> = BTreeMap::new();
+        for path in paths {
+            let path = normalize_path(path);
+            let prefix = path.clone() + "/";
+            let matched: Vec<(&String, &FileEntry)> = tracked
+                .iter()
+                .filter(|(x, _)| **x == path || x.starts_with(prefix.as_str()))
+                .collect();
+            if matched.is_empty() {
+                result.not_tracked.push(path);
+                continue;
+            }
+            files.extend(
+                matched
+                    .into_iter()
+                    .map(|(x, file)| (x.clone(), file.clone())),
+            );
+        }
+
+        if !cached && !force {
+            let mut changed: Vec<String> = Vec::new();
+            for (path, file) in &files {
+                let exists = fs::symlink_metadata(self.work_path(path)).is_ok();
+                let is_committed = committed.get(path) == Some(file);
+                if !is_committed || exists && self.is_modified(path, &file.hash)? {
+                    changed.push(path.clone());
+                }
+            }
+            if !changed.is_empty() {
+                return Err(Error::LocalChanges(changed));
+            }
+        }
+
+        // the files are deleted first, so the removal is only staged once they are gone.
+        if !cached {
+            for path in files.keys() {
+                match fs::remove_file(self.work_path(path)) {
+                    Ok(()) => self.remove_empty_parents(path),
+                    // a file where a folder of the path was: the file is not there.
+                    Err(err)
+                        if err.kind() == ErrorKind::NotFound
+                            || err.kind() == ErrorKind::NotADirectory => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        for path in files.keys() {
+            match committed.get(path) {
+                Some(file) => index.insert(path, IndexEntry::removal(file)),
+                None => index.remove(path),
+            }
+        }
+        index.save()?;
+        result.removed = files.into_keys().collect();
+
+        Ok(result)
+    }
+
+    /// Moves a tracked file or folder to `destination`, or into it when it is a folder, and
+    /// stages the files under their new path along with the removal of the old one. The
+    /// move is a single rename and the index is written once. Existing files are only
+    /// replaced with `force`. Returns the moved files as (old path, new path).
+    pub fn move_files(
+        &self,
+        source: &str,
+        destination: &str,
+        force: bool,
+    ) -> Result<Vec<(String, String)>> {
+        let source = normalize_path(source);
+        let mut destination = normalize_path(destination);
+        let source_path = self.work_path(source.as_str());
+        if fs::symlink_metadata(self.work_path(destination.as_str())).is_ok_and(|x| x.is_dir()) {
+            let name = source.rsplit('/').next().unwrap_or_default();
+            destination = normalize_path((destination + "/" + name).as_str());
+        }
+        let destination_path = self.work_path(destination.as_str());
+
+        let committed = self.read_commit_files(self.head_commit()?.as_deref())?;
+        let mut index = self.index()?;
+        let tracked = tracked_files(&committed, &index);
+        let prefix = source.clone() + "/";
+        let mut moves: Vec<(String, String)> = Vec::new();
+        for path in tracked.keys() {
+            if *path == source {
+                moves.push((path.clone(), destination.clone()));
+            } else if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                moves.push((path.clone(), destination.clone() + "/" + rest));
+            }
+        }
+        if moves.is_empty() || fs::symlink_metadata(&source_path).is_err() {
+            return Err(Error::NotTracked(source));
+        }
+        if !force {
+            let exists = fs::symlink_metadata(&destination_path).is_ok();
+            if let Some((_, target)) = moves
+                .iter()
+                .find(|(_, target)| exists || tracked.contains_key(target))
+            {
+                return Err(Error::AlreadyExists(target.clone()));
+            }
+        }
+
+        if let Some(parent) = destination_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&source_path, &destination_path)?;
+        for (path, target) in &moves {
+            // the working file may differ from the tracked version, status hashes it again.
+            index.insert(target, IndexEntry::without_stat(&tracked[path]));
+            match committed.get(path) {
+                Some(file) => index.insert(path, IndexEntry::removal(file)),
+                None => index.remove(path),
+            }
+        }
+        // put the files back rather than leave them where the index does not expect them.
+        if let Err(err) = index.save() {
+            let _ = fs::rename(&destination_path, &source_path);
+            return Err(err);
+        }
+
+        Ok(moves)
+    }
+
+    /// Writes the blob of a working file and returns its index entry.
+    fn store_file(&self, path: &str) -> Result<IndexEntry> {
+        let file_path = self.work_path(path);
+        // stat before reading, so a change made while reading does not get the stat data
+        // of the new content with the hash of the old one.
+        let metadata = fs::symlink_metadata(&file_path)?;
+        let hash = self.objects().write_file_blob(&file_path)?;
+
+        Ok(IndexEntry::new(hash.as_str(), &metadata))
+    }
+
+    fn collect_files(
+        &self,
+        path: &str,
+        ignored: &[String],
+        files: &mut BTreeSet<String>,
+    ) -> Result<()> {
+        if ignored.iter().any(|x| x == path) {
+            return Ok(());
+        }
+        // symbolic links are stored as links, never followed, so a link to a folder cannot
+        // lead outside the working directory or into a cycle.
+        let file_path = self.work_path(path);
+        if !fs::symlink_metadata(&file_path)?.is_dir() {
+            files.insert(path.to_string());
+            return Ok(());
+        }
+
+        let mut entries: Vec<String> = Vec::new();
+        for entry in fs::read_dir(&file_path)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if name == PIT_FOLDER {
+                continue;
+            }
+            entries.push(normalize_path(
+                (path.to_string() + "/" + name.as_str()).as_str(),
+            ));
+        }
+        for entry in entries {
+            self.collect_files(entry.as_str(), ignored, files)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The files the next commit holds: the committed ones with the staged files and without
+/// the staged removals.
+pub(crate) fn tracked_files(
+    committed: &BTreeMap<String, FileEntry>,
+    index: &Index,
+) -> BTreeMap<String, FileEntry> {
+    let mut tracked = committed.clone();
+    tracked.extend(
+        index
+            .staged()
+            .map(|(path, entry)| (path.clone(), entry.file())),
+    );
+    for path in index.removed() {
+        tracked.remove(path);
+    }
+
+    tracked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TemporaryRepository;
+
+    fn committed_paths(repository: &Repository) -> Vec<String> {
+        let head = repository.head_commit().unwrap();
+        let files = repository.read_commit_files(head.as_deref()).unwrap();
+        files.into_keys().collect()
+    }
+
+    #[test]
+    fn stages_a_file_in_place_of_a_folder() {
+        let repository = TemporaryRepository::new("staging", "file");
+        repository.write_file("a/x", "x");
+        repository.write_file("a/y", "y");
+        repository.commit_files(&["a"], "folder");
+        fs::remove_dir_all(repository.work_path("a")).unwrap();
+        repository.write_file("a", "file");
+
+        repository.commit_files(&["a"], "file");
+
+        assert_eq!(committed_paths(&repository), ["./a"]);
+    }
+
+    #[test]
+    fn stages_a_folder_in_place_of_a_file() {
+        let repository = TemporaryRepository::new("staging", "folder");
+        repository.write_file("a", "file");
+        repository.commit_files(&["a"], "file");
+        fs::remove_file(repository.work_path("a")).unwrap();
+        repository.write_file("a/x", "x");
+
+        repository.commit_files(&["a"], "folder");
+
+        assert_eq!(committed_paths(&repository), ["./a/x"]);
+    }
+
+    #[test]
+    fn removes_files_and_stages_their_removal() {
+        let repository = TemporaryRepository::new("staging", "remove");
+        repository.write_file("a", "a");
+        repository.write_file("b/c", "c");
+        repository.commit_files(&["a", "b"], "first");
+
+        let result = repository.remove(&["b".to_string()], false, false).unwrap();
+
+        assert_eq!(result.removed, ["./b/c"]);
+        assert!(!repository.work_path("b").exists());
+        repository.commit("remove").unwrap();
+        assert_eq!(committed_paths(&repository), ["./a"]);
+    }
+
+    #[test]
+    fn keeps_changed_files_unless_forced() {
+        let repository = TemporaryRepository::new("staging", "remove-changed");
+        repository.write_file("a", "a");
+        repository.commit_files(&["a"], "first");
+        repository.write_file("a", "changed");
+        repository.write_file("new", "new");
+        repository.add(&["new".to_string()]).unwrap();
+
+        let refused = repository.remove(&["a".to_string()], false, false);
+        assert!(matches!(refused, Err(Error::LocalChanges(paths)) if paths == ["./a"]));
+        assert!(repository.work_path("a").exists());
+        repository
+            .remove(&["new".to_string()], true, false)
+            .unwrap();
+        repository.remove(&["a".to_string()], false, true).unwrap();
+
+        assert!(!repository.work_path("a").exists());
+        assert!(repository.work_path("new").exists());
+        assert!(repository.staged_files().unwrap().is_empty());
+        assert_eq!(repository.removed_files().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn removes_a_file_whose_folder_became_a_file() {
+        let repository = TemporaryRepository::new("staging", "remove-folder");
+        repository.write_file("a/x", "x");
+        repository.commit_files(&["a"], "first");
+        fs::remove_dir_all(repository.work_path("a")).unwrap();
+        repository.write_file("a", "file");
+
+        repository
+            .remove(&["a/x".to_string()], false, false)
+            .unwrap();
+
+        assert!(repository.removed_files().unwrap().contains("./a/x"));
+        assert_eq!(
+            fs::read_to_string(repository.work_path("a")).unwrap(),
+            "file"
+        );
+    }
+
+    #[test]
+    fn moves_a_folder_and_stages_the_move() {
+        let repository = TemporaryRepository::new("staging", "move");
+        repository.write_file("d/a", "a");
+        repository.write_file("d/b", "b");
+        repository.write_file("t/x", "x");
+        repository.commit_files(&["d", "t"], "first");
+
+        let moves = repository.move_files("d", "e", false).unwrap();
+        let moved_into = repository.move_files("e/a", "t", false).unwrap();
+
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0], ("./d/a".to_string(), "./e/a".to_string()));
+        assert_eq!(moved_into, [("./e/a".to_string(), "./t/a".to_string())]);
+        assert_eq!(
+            fs::read_to_string(repository.work_path("t/a")).unwrap(),
+            "a"
+        );
+        repository.commit("move").unwrap();
+        assert_eq!(committed_paths(&repository), ["./e/b", "./t/a", "./t/x"]);
+    }
+
+    #[test]
+    fn replaces_files_only_when_forced() {
+        let repository = TemporaryRepository::new("staging", "move-existing");
+        repository.write_file("a", "a");
+        repository.write_file("b", "b");
+        repository.commit_files(&["a", "b"], "first");
+        repository.write_file("untracked", "u");
+
+        let refused = repository.move_files("a", "b", false);
+        assert!(matches!(refused, Err(Error::AlreadyExists(path)) if path == "./b"));
+        let untracked = repository.move_files("untracked", "c", false);
+        assert!(matches!(untracked, Err(Error::NotTracked(path)) if path == "./untracked"));
+        repository.move_files("a", "b", true).unwrap();
+
+        assert_eq!(fs::read_to_string(repository.work_path("b")).unwrap(), "a");
+        repository.commit("move").unwrap();
+        assert_eq!(committed_paths(&repository), ["./b"]);
+    }
+}
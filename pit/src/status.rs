@@ -0,0 +1,216 @@
+use crate::error::{Error, Result};
+use crate::index::{file_mode, Index, IndexEntry};
+use crate::object::{hash_file, read_work_file};
+use crate::rename::{Rename, DEFAULT_RENAME_THRESHOLD};
+use crate::repository::{FileEntry, Repository, PIT_FOLDER};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+/// Differences between the last commit, the index and the working directory.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Staged files that are not in the last commit.
+    pub staged_added: Vec<String>,
+    /// Staged files that replace a file of the last commit.
+    pub staged_modified: Vec<String>,
+    /// Files of the last commit the next commit removes.
+    pub staged_deleted: Vec<String>,
+    /// Staged files that come from a file of the last commit, moved or copied.
+    pub renamed: Vec<Rename>,
+    /// Tracked files whose content differs from the committed or staged version.
+    pub modified: Vec<String>,
+    /// Tracked files with the same content but another mode, like a script made executable.
+    pub mode_changed: Vec<String>,
+    /// Tracked files missing from the working directory.
+    pub deleted: Vec<String>,
+    /// Files and folders pit does not track.
+    pub untracked: Vec<String>,
+    /// Files left with conflicts by an unfinished merge.
+    pub unmerged: Vec<String>,
+}
+
+impl Repository {
+    /// Files whose stat data matches the index are not read again. The stat data of the
+    /// files that had to be hashed is saved to the index for the next status.
+    pub fn status(&self) -> Result<Status> {
+        self.status_with_renames(DEFAULT_RENAME_THRESHOLD)
+    }
+
+    /// Like `status`, reporting staged files at least `threshold` percent similar to a
+    /// committed file as renamed or copied.
+    pub fn status_with_renames(&self, threshold: u8) -> Result<Status> {
+        let mut status = Status::default();
+        let mut index = self.index()?;
+        let committed = self.read_commit_files(self.head_commit()?.as_deref())?;
+        let mut tracked = committed.clone();
+        for (path, entry) in index.staged() {
+            if tracked.contains_key(path) {
+                status.staged_modified.push(path.clone());
+            } else {
+                status.staged_added.push(path.clone());
+            }
+            tracked.insert(path.clone(), entry.file());
+        }
+        for path in index.removed() {
+            tracked.remove(path);
+            status.staged_deleted.push(path.clone());
+        }
+        status.renamed = self.detect_renames(&committed, &tracked, threshold)?;
+        for rename in &status.renamed {
+            status.staged_added.retain(|x| *x != rename.to);
+            if !rename.copy {
+                status.staged_deleted.retain(|x| *x != rename.from);
+            }
+        }
+
+        let mut missing: BTreeSet<String> = tracked.keys().cloned().collect();
+        self.add_working_tree_status(".", &tracked, &mut index, &mut missing, &mut status)?;
+        status.deleted = missing.into_iter().collect();
+        status.unmerged = self.merge_conflicts()?;
+
+        let untracked: Vec<String> = index
+            .entries()
+            .iter()
+            .filter(|(path, entry)| !entry.removed && !tracked.contains_key(*path))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in untracked {
+            index.remove(path.as_str());
+        }
+        if index.is_changed() {
+            index.save()?;
+        }
+
+        Ok(status)
+    }
+
+    /// Returns the staged files, the modified tracked files and the untracked files that
+    /// writing `target_files` would replace. Other untracked files are not included because
+    /// commands that rewrite the working tree leave them alone.
+    pub fn uncommitted_changes(
+        &self,
+        target_files: &BTreeMap<String, FileEntry>,
+    ) -> Result<Vec<String>> {
+        let status = self.status()?;
+        let committed = self.read_commit_files(self.head_commit()?.as_deref())?;
+        let mut changes: Vec<String> = Vec::new();
+        changes.extend(status.staged_added);
+        changes.extend(status.staged_modified);
+        changes.extend(status.staged_deleted);
+        for rename in status.renamed {
+            if !rename.copy {
+                changes.push(rename.from);
+            }
+            changes.push(rename.to);
+        }
+        changes.extend(status.modified);
+        changes.extend(status.mode_changed);
+        changes.extend(status.deleted);
+        changes.extend(self.overwritten_untracked_files(&committed, target_files));
+        changes.sort();
+        changes.dedup();
+
+        Ok(changes)
+    }
+
+    /// Tells whether the working file at `path` differs from the blob `hash`. The file is
+    /// hashed through a fixed-size buffer, never read whole.
+    pub fn is_modified(&self, path: &str, hash: &str) -> Result<bool> {
+        let file_path = self.work_path(path);
+        match hash_file(&file_path) {
+            Ok(file_hash) if file_hash == hash => return Ok(false),
+            Ok(_) => {}
+            Err(_) => return Ok(true),
+        }
+
+        // blobs written in the old format have another hash for the same content.
+        match self.objects().read_header(hash) {
+            Ok(None) => Ok(self.read_blob_content(hash)? != read_work_file(&file_path)?),
+            Ok(Some(_)) | Err(Error::ObjectNotFound(_)) => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Like `is_modified`, but trusts the stat data of the index when it is up to date and
+    /// records it when the file had to be hashed and did not change.
+    fn is_tracked_file_modified(&self, index: &mut Index, path: &str, hash: &str) -> Result<bool> {
+        // stat before reading, so a change made while hashing is seen by the next status.
+        let metadata = match fs::symlink_metadata(self.work_path(path)) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(true),
+        };
+        if index.is_up_to_date(path, hash, &metadata) {
+            return Ok(false);
+        }
+        if self.is_modified(path, hash)? {
+            return Ok(true);
+        }
+
+        let mut entry = IndexEntry::new(hash, &metadata);
+        entry.staged = index.get(path).is_some_and(|x| x.staged);
+        index.insert(path, entry);
+
+        Ok(false)
+    }
+
+    fn add_working_tree_status(
+        &self,
+        path: &str,
+        tracked: &BTreeMap<String, FileEntry>,
+        index: &mut Index,
+        missing: &mut BTreeSet<String>,
+        status: &mut Status,
+    ) -> Result<()> {
+        let mut entries: Vec<(String, bool)> = Vec::new();
+        for entry in fs::read_dir(self.work_path(path))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == PIT_FOLDER {
+                continue;
+            }
+            entries.push((
+                path.to_string() + "/" + name.as_str(),
+                entry.file_type()?.is_dir(),
+            ));
+        }
+        entries.sort();
+
+        for (entry_path, is_dir) in entries {
+            if is_dir {
+                let prefix = entry_path.clone() + "/";
+                let is_tracked = tracked
+                    .range(prefix.clone()..)
+                    .next()
+                    .is_some_and(|(x, _)| x.starts_with(prefix.as_str()));
+                if is_tracked {
+                    self.add_working_tree_status(
+                        entry_path.as_str(),
+                        tracked,
+                        index,
+                        missing,
+                        status,
+                    )?;
+                } else {
+                    status.untracked.push(entry_path);
+                }
+                continue;
+            }
+
+            match tracked.get(&entry_path) {
+                Some(file) => {
+                    missing.remove(&entry_path);
+                    if self.is_tracked_file_modified(index, entry_path.as_str(), &file.hash)? {
+                        status.modified.push(entry_path);
+                    } else if fs::symlink_metadata(self.work_path(entry_path.as_str()))
+                        .is_ok_and(|x| file_mode(&x) != file.mode)
+                    {
+                        status.mode_changed.push(entry_path);
+                    }
+                }
+                None => status.untracked.push(entry_path),
+            }
+        }
+
+        Ok(())
+    }
+}
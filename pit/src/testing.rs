@@ -0,0 +1,70 @@
+//! Fixtures shared by the unit tests.
+
+use crate::repository::Repository;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// An empty folder in the temporary directory, deleted when dropped. Its name holds the
+/// process id so concurrent test runs never share it.
+pub(crate) struct TemporaryFolder {
+    path: PathBuf,
+}
+
+impl TemporaryFolder {
+    pub fn new(area: &str, name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("pit-{}-{}-{}", area, name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TemporaryFolder { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TemporaryFolder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A repository initialized in a `TemporaryFolder`.
+pub(crate) struct TemporaryRepository {
+    repository: Repository,
+    _folder: TemporaryFolder,
+}
+
+impl TemporaryRepository {
+    pub fn new(area: &str, name: &str) -> Self {
+        let folder = TemporaryFolder::new(area, name);
+        TemporaryRepository {
+            repository: Repository::init(folder.path()).unwrap(),
+            _folder: folder,
+        }
+    }
+
+    /// Writes a working file, creating its folders.
+    pub fn write_file(&self, path: &str, content: &str) {
+        let file_path = self.work_path(path);
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(file_path, content).unwrap();
+    }
+
+    /// Stages the given paths and commits them.
+    pub fn commit_files(&self, paths: &[&str], message: &str) -> String {
+        let paths: Vec<String> = paths.iter().map(|x| x.to_string()).collect();
+        self.add(&paths).unwrap();
+        self.commit(message).unwrap().unwrap()
+    }
+}
+
+impl Deref for TemporaryRepository {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repository
+    }
+}
@@ -0,0 +1,560 @@
+use crate::error::{Error, Result};
+use crate::index::{file_mode, IndexEntry};
+use crate::object::{hash_file, FileMode};
+use crate::refs::Ref;
+use crate::repository::{is_pit_path, normalize_path, FileEntry, Repository};
+use crate::staging::tracked_files;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Files written or removed while updating the working directory.
+#[derive(Debug, Default)]
+pub struct TreeChanges {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// Result of `Repository::restore`.
+#[derive(Debug, Default)]
+pub struct RestoreResult {
+    /// Index entries set back to the restored version.
+    pub staged: Vec<String>,
+    pub changes: TreeChanges,
+    /// Paths that match no file of the restored version.
+    pub not_found: Vec<String>,
+}
+
+impl TreeChanges {
+    pub fn len(&self) -> usize {
+        self.created.len() + self.updated.len() + self.deleted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Repository {
+    /// Rewrites the working directory so that the files of `old_files` become the files of
+    /// `new_files`. Files whose content and mode are already right are left alone. Nothing
+    /// is written when a file of `new_files` would replace an untracked file.
+    pub fn update_working_tree(
+        &self,
+        old_files: &BTreeMap<String, FileEntry>,
+        new_files: &BTreeMap<String, FileEntry>,
+    ) -> Result<TreeChanges> {
+        let untracked = self.overwritten_untracked_files(old_files, new_files);
+        if !untracked.is_empty() {
+            return Err(Error::UntrackedFiles(untracked));
+        }
+
+        self.write_working_tree(old_files, new_files)
+    }
+
+    /// Lists the files only `new_files` has that are already in the working directory with
+    /// another content, or are folders there. A file below an untracked link or file is
+    /// listed as well, since writing it would follow the link or replace the file.
+    pub(crate) fn overwritten_untracked_files(
+        &self,
+        old_files: &BTreeMap<String, FileEntry>,
+        new_files: &BTreeMap<String, FileEntry>,
+    ) -> Vec<String> {
+        new_files
+            .iter()
+            .filter(|(path, file)| {
+                if is_pit_path(path) {
+                    return false;
+                }
+                if let Some(parent) = self.non_folder_parent(path) {
+                    return !old_files.contains_key(&parent);
+                }
+                if old_files.contains_key(*path) {
+                    return false;
+                }
+                let file_path = self.work_path(path);
+                match fs::symlink_metadata(&file_path) {
+                    Ok(metadata) if metadata.is_dir() => {
+                        self.holds_untracked_files(path, old_files)
+                    }
+                    Ok(_) => hash_file(&file_path).map_or(true, |x| x != file.hash),
+                    Err(_) => false,
+                }
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Whether the folder at `path` holds anything but files of `old_files`, which are
+    /// deleted before it is replaced. Empty folders do not count.
+    fn holds_untracked_files(&self, path: &str, old_files: &BTreeMap<String, FileEntry>) -> bool {
+        let Ok(entries) = fs::read_dir(self.work_path(path)) else {
+            return true;
+        };
+        entries.into_iter().any(|entry| {
+            let Ok(entry) = entry else {
+                return true;
+            };
+            let entry_path = path.to_string() + "/" + entry.file_name().to_string_lossy().as_ref();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => {
+                    self.holds_untracked_files(&entry_path, old_files)
+                }
+                _ => !old_files.contains_key(&entry_path),
+            }
+        })
+    }
+
+    /// Same as `update_working_tree`, replacing untracked files as well.
+    pub(crate) fn write_working_tree(
+        &self,
+        old_files: &BTreeMap<String, FileEntry>,
+        new_files: &BTreeMap<String, FileEntry>,
+    ) -> Result<TreeChanges> {
+        // check every object first so a missing one does not leave half a checkout behind.
+        for file in new_files.values() {
+            if !self.objects().contains(&file.hash) {
+                return Err(Error::ObjectNotFound(file.hash.clone()));
+            }
+        }
+
+        let mut changes = TreeChanges::default();
+        // removed files go first, so a folder they leave empty can become a file or a link.
+        for path in old_files.keys() {
+            if new_files.contains_key(path) || is_pit_path(path) {
+                continue;
+            }
+            // the file is not there if a parent is not a folder; never delete through a link.
+            if self.non_folder_parent(path).is_some() {
+                changes.deleted.push(path.clone());
+                continue;
+            }
+            if fs::remove_file(self.work_path(path)).is_ok() {
+                self.remove_empty_parents(path);
+            }
+            changes.deleted.push(path.clone());
+        }
+
+        for (path, file) in new_files {
+            if is_pit_path(path) {
+                continue;
+            }
+            let file_path = self.work_path(path);
+            // a link or a file where a folder of the path goes is replaced, not written
+            // through. The links left are untracked ones the caller agreed to replace.
+            if let Some(parent) = self.non_folder_parent(path) {
+                fs::remove_file(self.work_path(&parent))?;
+            }
+            if fs::symlink_metadata(&file_path).is_ok_and(|x| x.is_dir()) {
+                remove_empty_folders(&file_path);
+            }
+            let metadata = fs::symlink_metadata(&file_path).ok();
+            let is_link = metadata
+                .as_ref()
+                .is_some_and(|x| x.file_type().is_symlink());
+            // hashing reads the working file through a fixed-size buffer, never whole.
+            let same_content =
+                metadata.is_some() && hash_file(&file_path).is_ok_and(|x| x == file.hash);
+            let same_mode = metadata.is_some_and(|x| file_mode(&x) == file.mode);
+            if same_content && same_mode {
+                continue;
+            }
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // a link is replaced, never written through.
+            if is_link || file.mode == FileMode::Symlink && file_path.exists() {
+                fs::remove_file(&file_path)?;
+            }
+            if file.mode == FileMode::Symlink {
+                let target = self.read_blob_content(&file.hash)?;
+                write_link_or_file(&file_path, &target, file.mode)?;
+            } else {
+                if is_link || !same_content {
+                    self.objects().copy_blob_to_file(&file.hash, &file_path)?;
+                }
+                set_file_mode(&file_path, file.mode)?;
+            }
+            if old_files.contains_key(path) {
+                changes.updated.push(path.clone());
+            } else {
+                changes.created.push(path.clone());
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// The first folder of `path` that is a symbolic link or a file in the working
+    /// directory, if any.
+    fn non_folder_parent(&self, path: &str) -> Option<String> {
+        let mut parent = String::new();
+        let components: Vec<&str> = path.split('/').collect();
+        for component in &components[..components.len() - 1] {
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(component);
+            if *component == "." {
+                continue;
+            }
+            match fs::symlink_metadata(self.work_path(&parent)) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => return Some(parent),
+                Err(_) => return None,
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn remove_empty_parents(&self, path: &str) {
+        let mut parent = Path::new(path).parent();
+        while let Some(folder) = parent {
+            if folder.as_os_str().is_empty() || folder == Path::new(".") {
+                break;
+            }
+            // remove_dir only succeeds on empty folders.
+            if fs::remove_dir(self.root().join(folder)).is_err() {
+                break;
+            }
+            parent = folder.parent();
+        }
+    }
+
+    /// Switches to `branch`, writing its last commit to the working directory. With `create`
+    /// a missing branch is created from the current commit. Uncommitted changes, and
+    /// untracked files the branch has, are only overwritten with `force`. A branch at the
+    /// current commit keeps them.
+    pub fn checkout(&self, branch: &str, create: bool, force: bool) -> Result<TreeChanges> {
+        if self.is_merging() {
+            return Err(Error::MergeInProgress);
+        }
+        let current_commit = self.head_commit()?;
+        let target = Ref::branch(branch);
+        let exists = self.ref_exists(&target);
+        if !exists && !create {
+            return Err(Error::BranchNotFound(branch.to_string()));
+        }
+        // a new branch starts from the commit we are currently on.
+        let target_commit = match exists {
+            true => self.read_ref(&target)?,
+            false => current_commit.clone(),
+        };
+
+        if !exists {
+            self.write_ref(&target, current_commit.clone().unwrap_or_default().as_str())?;
+        }
+        // the working files and the index are kept, as nothing would replace them.
+        if target_commit == current_commit {
+            self.set_head(&target)?;
+            return Ok(TreeChanges::default());
+        }
+
+        let old_files = self.read_commit_files(current_commit.as_deref())?;
+        let new_files = self.read_commit_files(target_commit.as_deref())?;
+        self.ensure_clean_working_tree(&new_files, force)?;
+        let changes = self.write_working_tree(&old_files, &new_files)?;
+        self.set_head(&target)?;
+        self.clear_staging()?;
+
+        Ok(changes)
+    }
+
+    /// Fails with the uncommitted changes, and the untracked files `new_files` would
+    /// replace, unless `force` allows discarding them.
+    pub(crate) fn ensure_clean_working_tree(
+        &self,
+        new_files: &BTreeMap<String, FileEntry>,
+        force: bool,
+    ) -> Result<()> {
+        if force {
+            return Ok(());
+        }
+        let changes = self.uncommitted_changes(new_files)?;
+        if !changes.is_empty() {
+            return Err(Error::LocalChanges(changes));
+        }
+
+        Ok(())
+    }
+
+    /// Sets files and folders back to another version. With `staged` their index entries
+    /// become the version of `source`, or of the last commit: staged files are unstaged and
+    /// staged removals dropped. With `worktree` the working files are rewritten from
+    /// `source`, or from the index when only the working files are restored, and files
+    /// missing from that version are deleted.
+    pub fn restore(
+        &self,
+        paths: &[String],
+        source: Option<&str>,
+        staged: bool,
+        worktree: bool,
+    ) -> Result<RestoreResult> {
+        let committed = self.read_commit_files(self.head_commit()?.as_deref())?;
+        let mut index = self.index()?;
+        let tracked = tracked_files(&committed, &index);
+        let source_files = match source {
+            Some(revision) => {
+                self.read_commit_files(Some(self.resolve_revision(revision)?.as_str()))?
+            }
+            None if staged => committed.clone(),
+            None => tracked.clone(),
+        };
+
+        let mut result = RestoreResult::default();
+        let mut matched: BTreeSet<String> = BTreeSet::new();
+        for path in paths {
+            let path = normalize_path(path);
+            let prefix = path.clone() + "/";
+            let matches = |x: &&String| **x == path || x.starts_with(prefix.as_str());
+            let count = matched.len();
+            matched.extend(source_files.keys().filter(matches).cloned());
+            // tracked files the restored version does not have are deleted.
+            if worktree {
+                matched.extend(tracked.keys().filter(matches).cloned());
+            }
+            if staged {
+                let changed = index.staged().map(|(x, _)| x).chain(index.removed());
+                matched.extend(changed.filter(matches).cloned().collect::<Vec<String>>());
+            }
+            if matched.len() == count {
+                result.not_found.push(path);
+            }
+        }
+
+        if staged {
+            for path in &matched {
+                let entry = match (source_files.get(path), committed.get(path)) {
+                    (Some(file), Some(committed_file)) if file == committed_file => IndexEntry {
+                        staged: false,
+                        ..IndexEntry::without_stat(file)
+                    },
+                    (Some(file), _) => IndexEntry::without_stat(file),
+                    (None, Some(committed_file)) => IndexEntry::removal(committed_file),
+                    (None, None) => {
+                        if index.get(path).is_some() {
+                            index.remove(path);
+                            result.staged.push(path.clone());
+                        }
+                        continue;
+                    }
+                };
+                // an entry already at that version keeps its stat data.
+                let current = index.get(path);
+                let unchanged = current.is_some_and(|x| {
+                    (x.file(), x.staged, x.removed) == (entry.file(), entry.staged, entry.removed)
+                });
+                if !unchanged {
+                    index.insert(path, entry);
+                    result.staged.push(path.clone());
+                }
+            }
+            index.save()?;
+        }
+        if worktree {
+            let select = |files: &BTreeMap<String, FileEntry>| -> BTreeMap<String, FileEntry> {
+                files
+                    .iter()
+                    .filter(|(path, _)| matched.contains(*path))
+                    .map(|(path, file)| (path.clone(), file.clone()))
+                    .collect()
+            };
+            // the paths were named, so untracked files there are replaced as git does.
+            result.changes = self.write_working_tree(&select(&tracked), &select(&source_files))?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Removes a folder and the folders below it as long as they hold no files.
+fn remove_empty_folders(path: &Path) {
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|x| x.is_dir()) {
+                remove_empty_folders(&entry.path());
+            }
+        }
+    }
+    // remove_dir only succeeds on empty folders.
+    let _ = fs::remove_dir(path);
+}
+
+/// Sets or clears the executable bits of a file. Other platforms have no such bits.
+fn set_file_mode(path: &Path, mode: FileMode) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(path)?.permissions();
+        let bits = permissions.mode();
+        let bits = match mode {
+            // executable by whoever can read the file.
+            FileMode::Executable => bits | (bits & 0o444) >> 2,
+            _ => bits & !0o111,
+        };
+        if bits != permissions.mode() {
+            permissions.set_mode(bits);
+            fs::set_permissions(path, permissions)?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+
+    Ok(())
+}
+
+/// Creates a symbolic link to `content` for the link mode, or a file holding `content`.
+/// Without symbolic links the file holds the target path, as git does.
+fn write_link_or_file(path: &Path, content: &[u8], mode: FileMode) -> Result<()> {
+    #[cfg(unix)]
+    if mode == FileMode::Symlink {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        return Ok(std::os::unix::fs::symlink(
+            OsStr::from_bytes(content),
+            path,
+        )?);
+    }
+    fs::write(path, content)?;
+
+    set_file_mode(path, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TemporaryFolder, TemporaryRepository};
+
+    #[cfg(unix)]
+    #[test]
+    fn never_writes_through_an_untracked_link() {
+        let repository = TemporaryRepository::new("worktree", "link");
+        repository.write_file("f", "f");
+        repository.commit_files(&["f"], "main");
+        repository.checkout("feature", true, false).unwrap();
+        repository.write_file("d/x", "x");
+        repository.commit_files(&["d"], "feature");
+        repository.checkout("main", false, false).unwrap();
+        let outside = TemporaryFolder::new("worktree", "outside");
+        std::os::unix::fs::symlink(outside.path(), repository.work_path("d")).unwrap();
+
+        let refused = repository.checkout("feature", false, false);
+        assert!(matches!(refused, Err(Error::LocalChanges(paths)) if paths == ["./d/x"]));
+        repository.checkout("feature", false, true).unwrap();
+
+        assert!(!outside.path().join("x").exists());
+        assert!(fs::symlink_metadata(repository.work_path("d"))
+            .unwrap()
+            .is_dir());
+        assert_eq!(
+            fs::read_to_string(repository.work_path("d/x")).unwrap(),
+            "x"
+        );
+    }
+
+    #[test]
+    fn replaces_a_folder_of_tracked_files_with_a_file() {
+        let repository = TemporaryRepository::new("worktree", "folder");
+        repository.write_file("a/x", "x");
+        repository.commit_files(&["a"], "folder");
+        repository.checkout("other", true, false).unwrap();
+        repository.remove(&["a".to_string()], false, false).unwrap();
+        repository.write_file("a", "file");
+        repository.commit_files(&["a"], "file");
+        repository.checkout("main", false, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(repository.work_path("a/x")).unwrap(),
+            "x"
+        );
+        fs::create_dir_all(repository.work_path("a/empty")).unwrap();
+
+        repository.checkout("other", false, false).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(repository.work_path("a")).unwrap(),
+            "file"
+        );
+        repository.checkout("main", false, false).unwrap();
+        repository.write_file("a/untracked", "u");
+        let refused = repository.checkout("other", false, false);
+        assert!(matches!(refused, Err(Error::LocalChanges(paths)) if paths == ["./a"]));
+    }
+
+    #[test]
+    fn keeps_local_changes_when_the_commit_does_not_change() {
+        let repository = TemporaryRepository::new("worktree", "same-commit");
+        repository.write_file("f", "a");
+        repository.commit_files(&["f"], "first");
+        repository.write_file("f", "b");
+        repository.write_file("g", "g");
+        repository.add(&["g".to_string()]).unwrap();
+
+        let changes = repository.checkout("feature", true, false).unwrap();
+
+        assert!(changes.is_empty());
+        assert_eq!(repository.head().unwrap(), Ref::branch("feature"));
+        assert_eq!(fs::read_to_string(repository.work_path("f")).unwrap(), "b");
+        assert!(repository.staged_files().unwrap().contains_key("./g"));
+    }
+
+    #[test]
+    fn restores_working_files_from_the_index() {
+        let repository = TemporaryRepository::new("worktree", "restore");
+        repository.write_file("f", "a");
+        repository.commit_files(&["f"], "first");
+        repository.write_file("f", "b");
+        repository.add(&["f".to_string()]).unwrap();
+        repository.write_file("f", "c");
+
+        let result = repository
+            .restore(&["f".to_string(), "missing".to_string()], None, false, true)
+            .unwrap();
+
+        assert_eq!(result.changes.updated, ["./f"]);
+        assert_eq!(result.not_found, ["./missing"]);
+        assert_eq!(fs::read_to_string(repository.work_path("f")).unwrap(), "b");
+    }
+
+    #[test]
+    fn unstages_files_without_touching_them() {
+        let repository = TemporaryRepository::new("worktree", "unstage");
+        repository.write_file("f", "a");
+        repository.commit_files(&["f"], "first");
+        repository.write_file("f", "b");
+        repository.write_file("g", "g");
+        repository.add(&["f".to_string(), "g".to_string()]).unwrap();
+
+        let result = repository
+            .restore(&[".".to_string()], None, true, false)
+            .unwrap();
+
+        assert_eq!(result.staged, ["./f", "./g"]);
+        assert!(repository.staged_files().unwrap().is_empty());
+        assert_eq!(fs::read_to_string(repository.work_path("f")).unwrap(), "b");
+        assert!(repository.work_path("g").exists());
+    }
+
+    #[test]
+    fn deletes_files_the_source_does_not_have() {
+        let repository = TemporaryRepository::new("worktree", "source");
+        repository.write_file("d/a", "a");
+        let old = repository.commit_files(&["d"], "old");
+        repository.write_file("d/a", "changed");
+        repository.write_file("d/new", "new");
+        repository.commit_files(&["d"], "new");
+
+        let result = repository
+            .restore(&["d".to_string()], Some(old.as_str()), false, true)
+            .unwrap();
+
+        assert_eq!(result.changes.updated, ["./d/a"]);
+        assert_eq!(result.changes.deleted, ["./d/new"]);
+        assert_eq!(
+            fs::read_to_string(repository.work_path("d/a")).unwrap(),
+            "a"
+        );
+        assert!(!repository.work_path("d/new").exists());
+    }
+}
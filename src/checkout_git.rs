@@ -1,12 +1,15 @@
-use crate::command::Command;
+use crate::command::{open_repository, Command};
+use crate::status_git::print_local_changes;
 use clap::Parser;
-use std::fs;
-use std::fs::File;
+use pit::TreeChanges;
 
 #[derive(Parser, Debug, Clone)]
 pub struct CheckoutArgs {
     branch: String,
     create: Option<bool>,
+    /// Switch branch even if it overwrites uncommitted changes.
+    #[arg(long)]
+    force: bool,
 }
 
 #[derive(Debug)]
@@ -22,43 +25,40 @@ impl CheckoutCommand {
 
 impl Command for CheckoutCommand {
     fn execute(&mut self) {
-        let checkout_ref = "./.pit/refs/".to_string() + &self.arguments.branch.clone();
-
-        let file = File::open(checkout_ref.clone());
-        if file.is_err() {
-            if self.arguments.create.is_none() {
-                return;
+        let Some(repository) = open_repository() else {
+            return;
+        };
+        let create = self.arguments.create.unwrap_or(false);
+        let branch = self.arguments.branch.as_str();
+        match repository.checkout(branch, create, self.arguments.force) {
+            Ok(changes) => {
+                print_tree_changes(&changes);
+                println!("Changed branch to {}", self.arguments.branch);
             }
-
-            let file_result = File::create(checkout_ref.clone());
-            if file_result.is_err() {
-                println!("Cannot create branch {:?}", file_result);
-                return;
+            Err(pit::Error::LocalChanges(paths)) => print_local_changes(&paths),
+            Err(err @ (pit::Error::BranchNotFound(_) | pit::Error::MergeInProgress)) => {
+                println!("{}", err)
             }
+            Err(err) => println!("Error happened when changing branch: {}", err),
         }
+    }
+}
 
-        // if self.arguments.get_current.is_some() {
-        //     let head = fs::read_to_string("./.pit/HEAD").unwrap();
-        //     println!("{}", head);
-        //     let commit_result = fs::read_to_string(".pit/".to_string() + &head);
-        //     if commit_result.is_err() {
-        //         println!("Cannot copy the current branch {:?}", commit_result);
-        //         return;
-        //     }
-        //     let _ = fs::write(checkout_ref.clone(), commit_result.unwrap());
-        // }
-
-        let result = fs::write(
-            "./.pit/HEAD",
-            "refs/".to_string() + &self.arguments.branch.clone(),
-        );
-
-        if result.is_err() {
-            println!("Error happened when changing branch {:?}", result);
-        }
-
-        let _ = fs::write("./.pit/objects/info", "");
-
-        println!("Changed branch to {}", self.arguments.branch);
+pub fn print_tree_changes(changes: &TreeChanges) {
+    for path in &changes.created {
+        println!(" {} created", path);
+    }
+    for path in &changes.updated {
+        println!(" {} updated", path);
+    }
+    for path in &changes.deleted {
+        println!(" {} deleted", path);
     }
+    println!(
+        "{} files changed, {} created, {} updated, {} deleted",
+        changes.len(),
+        changes.created.len(),
+        changes.updated.len(),
+        changes.deleted.len()
+    );
 }
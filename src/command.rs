@@ -1,3 +1,17 @@
+use pit::Repository;
+use std::path::Path;
+
 pub trait Command {
     fn execute(&mut self);
 }
+
+/// Opens the repository of the current folder, printing why when there is none.
+pub fn open_repository() -> Option<Repository> {
+    match Repository::open(Path::new("./")) {
+        Ok(repository) => Some(repository),
+        Err(err) => {
+            println!("{}", err);
+            None
+        }
+    }
+}
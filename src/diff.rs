@@ -1,41 +1,24 @@
-use crate::command::Command;
-use chksum_sha1 as sha1;
+use crate::command::{open_repository, Command};
 use clap::Parser;
 use color_print::cprint;
+use pit::index::file_mode;
+use pit::object::{is_binary, read_work_file};
+use pit::rename::DEFAULT_RENAME_THRESHOLD;
+use pit::{FileEntry, FileMode, Repository};
 use similar::{ChangeTag, TextDiff};
-use std::cell::RefCell;
-use std::collections::VecDeque;
 use std::fs;
-use std::fs::File;
-use std::rc::Rc;
+
 #[derive(Parser, Debug, Clone)]
 pub struct DiffArgs {
     commit: Option<String>,
     file: Option<String>,
-}
-type TreeNodeRef = Rc<RefCell<TreeInfo>>;
-#[derive(Debug, PartialEq, Clone, Default)]
-struct TreeInfo {
-    path: String,
-    hash: String,
-    pit_path: String,
-    parent: Option<TreeNodeRef>,
-    name: String,
-    type_of_file: String,
-    children: Vec<TreeNodeRef>,
-}
-impl TreeInfo {
-    fn new(path: String, name: String, parent: Option<TreeNodeRef>) -> Self {
-        TreeInfo {
-            children: Vec::new(),
-            path,
-            pit_path: Default::default(),
-            parent,
-            name,
-            type_of_file: Default::default(),
-            hash: "".to_string(),
-        }
-    }
+    /// Files larger than this many bytes are summarized instead of diffed line by line.
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    max_size: u64,
+    /// Files at least this similar (in percent) to a removed or existing file are shown as
+    /// renamed or copied.
+    #[arg(long, default_value_t = DEFAULT_RENAME_THRESHOLD, value_parser = clap::value_parser!(u8).range(0..=100))]
+    find_renames: u8,
 }
 
 #[derive(Debug)]
@@ -51,292 +34,203 @@ impl DiffCommand {
 
 impl Command for DiffCommand {
     fn execute(&mut self) {
-        let mut commit_code: String = get_commit_code(self.arguments.commit.clone());
-
-        let head_result = fs::read_to_string("./.pit/HEAD");
-        if head_result.is_err() {
-            println!("No head file. Fatal error");
-            return;
-        }
-        let head = head_result.unwrap();
-        let result = fs::read_to_string("./.pit/".to_string() + head.as_str());
-        if result.is_err() {
-            println!("Branch is corrupted!");
+        let Some(repository) = open_repository() else {
             return;
-        }
-        let current_commit = result.unwrap().clone();
-
-        if commit_code.is_empty() {
-            commit_code = current_commit.clone();
-        }
-        let mut root = construct_tree(current_commit.clone());
-        if current_commit == commit_code {
-            root = add_tracked_files(root.clone());
-            get_diff_file_tree(root.clone());
-        } else {
-            let second_tree = construct_tree(commit_code.clone());
-            get_diff_between_trees(root, second_tree);
+        };
+        let current_commit = match repository.head_commit() {
+            Ok(commit) => commit,
+            Err(err) => {
+                println!("Branch is corrupted! {}", err);
+                return;
+            }
+        };
+        let commit_code = match &self.arguments.commit {
+            Some(revision) => repository.resolve_revision(revision).ok(),
+            None => None,
+        };
+
+        let (max_size, find_renames) = (self.arguments.max_size, self.arguments.find_renames);
+        let result = match commit_code {
+            Some(commit) if Some(&commit) != current_commit.as_ref() => diff_commits(
+                &repository,
+                current_commit.as_deref(),
+                commit.as_str(),
+                max_size,
+                find_renames,
+            ),
+            _ => diff_working_tree(
+                &repository,
+                current_commit.as_deref(),
+                max_size,
+                find_renames,
+            ),
+        };
+        if let Err(err) = result {
+            println!("Cannot compute the diff: {}", err);
         }
     }
 }
 
-fn get_commit_code(head: Option<String>) -> String {
-    if head.is_none() {
-        return Default::default();
+/// Shows how the working files differ from their committed or staged version. Staged
+/// files that come from a committed file are shown as renamed or copied first.
+fn diff_working_tree(
+    repository: &Repository,
+    commit: Option<&str>,
+    max_size: u64,
+    find_renames: u8,
+) -> pit::Result<()> {
+    let committed = repository.read_commit_files(commit)?;
+    let mut files = committed.clone();
+    files.extend(repository.staged_files()?);
+    let removed = repository.removed_files()?;
+    for path in &removed {
+        files.remove(path);
     }
-    let head_string = head.unwrap().trim().to_string();
-    let heads_file_path = "./.pit/refs/".to_string() + head_string.clone().as_str();
-    let result = fs::read_to_string(heads_file_path);
-    if result.is_err() {
-        let commit_hash = File::open("./.pit/objects/".to_string() + head_string.clone().as_str());
-        if commit_hash.is_err() {
-            return Default::default();
-        } else {
-            return head_string;
+    let renamed = repository.detect_renames(&committed, &files, find_renames)?;
+    for path in removed {
+        if !renamed.iter().any(|x| !x.copy && x.from == path) {
+            println!(" {} was deleted", path);
         }
     }
 
-    result.unwrap().trim().to_string()
-}
-
-fn construct_tree(commit: String) -> TreeNodeRef {
-    let objects_file_path = String::from("./.pit/objects/");
-    let root = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-        "./".to_string(),
-        "./".to_string(),
-        None,
-    )));
-
-    let commit_content_result = fs::read_to_string(objects_file_path.clone() + commit.as_str());
-    if commit_content_result.is_err() {
-        return root;
-    }
-
-    let commit_content = commit_content_result.unwrap();
-    let tree_line: Vec<&str> = commit_content.lines().next().unwrap().split(' ').collect();
-    root.borrow_mut().pit_path = objects_file_path.clone() + tree_line[1].clone();
-    root.borrow_mut().hash = tree_line[1].clone().to_string();
-    root.borrow_mut().type_of_file = "tree".to_string();
-    // we consider the first tree file as the root of our changes.
-    let mut deque: VecDeque<TreeNodeRef> = VecDeque::new();
-    deque.push_back(root.clone());
-    while !deque.is_empty() {
-        let current = deque.pop_front().unwrap();
-        let current_content_result = fs::read_to_string(current.borrow().pit_path.clone());
-        if current_content_result.is_err() {
-            continue;
+    for (path, file) in files {
+        if let Some(rename) = renamed.iter().find(|x| x.to == path) {
+            println!("{}", rename);
         }
-
-        let current_content = current_content_result.unwrap();
-        let lines = current_content.lines();
-
-        if current.borrow().type_of_file == "tree" {
-            for line in lines {
-                if line.is_empty() {
-                    continue;
-                }
-                let data_line: Vec<&str> = line.split(' ').collect();
-
-                if data_line.len() != 3 {
-                    continue;
-                }
-
-                let new_node = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-                    data_line[2].to_string(),
-                    data_line[2].to_string(),
-                    Some(current.clone()),
-                )));
-                new_node.borrow_mut().hash = data_line[1].to_string();
-                new_node.borrow_mut().pit_path = objects_file_path.clone() + data_line[1];
-                new_node.borrow_mut().type_of_file = data_line[0].to_string();
-                current.borrow_mut().children.push(new_node.clone());
-                deque.push_back(new_node);
+        let file_path = repository.work_path(path.as_str());
+        let metadata = match fs::symlink_metadata(&file_path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                println!(" {} was deleted", path);
+                continue;
             }
+        };
+        if file_mode(&metadata) != file.mode {
+            print_mode_change(path.as_str(), file.mode, file_mode(&metadata));
         }
-    }
-
-    root
-}
-
-fn add_tracked_files(root: TreeNodeRef) -> TreeNodeRef {
-    let objects_file_path = String::from("./.pit/objects/");
-
-    let cached_files_result = fs::read_to_string(objects_file_path.clone() + "info");
-    if cached_files_result.is_err() {
-        return root;
-    }
-    let cached_files = cached_files_result.unwrap();
-    for cached_file in cached_files.lines() {
-        if cached_file.is_empty() {
+        if !repository.is_modified(path.as_str(), file.hash.as_str())? {
             continue;
         }
-        let cached_file_content_result =
-            fs::read_to_string(objects_file_path.clone() + cached_file);
-        if cached_file_content_result.is_err() {
+        let (old_size, size) = (repository.blob_size(file.hash.as_str())?, metadata.len());
+        if old_size > max_size || size > max_size {
+            print_size_change(path.as_str(), old_size, size);
             continue;
         }
-        let content = cached_file_content_result.unwrap();
-        let lines: Vec<&str> = content.lines().collect();
-        // last 3 lines in order file_path "empty" file_type
-        let file_name = lines[lines.len() - 3];
-        let mut node = root.clone();
-        let mut is_added = false;
-        let file_paths: Vec<String> = file_name.split('/').map(|x| x.to_string()).collect();
-        let mut idx = 0;
-        loop {
-            if idx == file_paths.len() {
-                break;
-            }
-            if file_paths[idx] == "." {
-                idx += 1;
-                continue;
-            }
-            let mut next_node: Option<TreeNodeRef> = None;
-            for child in &node.borrow().children {
-                let child_data = child.borrow();
-                let corresponding_name = child_data.name.split('/').take(idx + 2).last();
-                if corresponding_name.is_none() {
-                    continue;
-                }
-                if file_paths[idx] == corresponding_name.unwrap() {
-                    next_node = Some(child.clone());
-                }
-            }
-            if next_node.is_none() {
-                is_added = true;
-                let path = node.borrow().path.clone().trim_matches('/').to_string()
-                    + "/"
-                    + file_paths[idx].as_str();
-                let added_node = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-                    path.clone(),
-                    path,
-                    Some(node.clone()),
-                )));
-                if idx == file_paths.len() - 1 {
-                    added_node.borrow_mut().hash = cached_file.to_string();
-                    added_node.borrow_mut().pit_path = objects_file_path.clone() + cached_file;
-                    added_node.borrow_mut().type_of_file = "blob".to_string();
-                } else {
-                    added_node.borrow_mut().type_of_file = "tree".to_string();
-                }
-                node.borrow_mut().children.push(added_node.clone());
-                node = added_node;
-                idx += 1;
-                continue;
-            }
-            node = next_node.unwrap();
-            idx += 1;
-        }
-        if is_added {
-        } else {
-            node.borrow_mut().hash = cached_file.to_string();
-            node.borrow_mut().pit_path = objects_file_path.clone() + cached_file;
+        let old_content = repository.read_blob_content(file.hash.as_str())?;
+        let content = read_work_file(&file_path)?;
+        if file.mode == FileMode::Symlink && file_mode(&metadata) == FileMode::Symlink {
+            print_link_change(path.as_str(), &old_content, &content);
+            continue;
         }
+        print_diff(path.as_str(), &old_content, &content);
     }
 
-    root
+    Ok(())
 }
 
-fn get_diff_file_tree(root: TreeNodeRef) {
-    if root.borrow().type_of_file == "tree" {
-        for child in &root.borrow().children {
-            get_diff_file_tree(child.clone());
-        }
-    } else {
-        let path_borrowed = root.borrow().path.clone();
-        let content_result = fs::read_to_string(path_borrowed.clone());
-        if content_result.is_err() {
-            println!(" {} was deleted", path_borrowed);
-            return;
-        }
-        let pit_content_result = fs::read_to_string(root.borrow().pit_path.clone());
-        if pit_content_result.is_err() {
-            return;
-        }
-        let pit_content_string = pit_content_result.unwrap();
-        let pit_content: Vec<&str> = pit_content_string.lines().collect();
-        let name_of_file = pit_content[pit_content.len() - 3];
-        let mut content = content_result.unwrap();
-        let content_copy = content.clone();
-        let pit_content_file = &pit_content[..pit_content.len() - 3];
-        content.push_str("\n\n");
-        content.push_str(name_of_file);
-        content.push_str("\n\nblob");
-        let digest = sha1::chksum(content).unwrap().to_hex_lowercase();
-        if root.borrow().hash.clone() != digest {
-            let pit_text_content = pit_content_file.join("\n\n");
-            let content1 = content_copy.as_str();
-            let content2 = pit_text_content.as_str();
-            let diff = TextDiff::from_lines(content2, content1);
+/// Shows how the files of the current commit differ from `other_commit`.
+fn diff_commits(
+    repository: &Repository,
+    commit: Option<&str>,
+    other_commit: &str,
+    max_size: u64,
+    find_renames: u8,
+) -> pit::Result<()> {
+    let files = repository.read_commit_files(commit)?;
+    let other_files = repository.read_commit_files(Some(other_commit))?;
+    let changes = repository.compare_files(&other_files, &files, find_renames)?;
+
+    for path in &changes.added {
+        println!("{} was added", path);
+    }
+    for path in &changes.deleted {
+        println!("{} was deleted", path);
+    }
+    for rename in &changes.renamed {
+        println!("{}", rename);
+        let (old, new) = (&other_files[&rename.from], &files[&rename.to]);
+        print_file_change(repository, rename.to.as_str(), old, new, max_size)?;
+    }
+    for path in &changes.modified {
+        let (old, new) = (&other_files[path], &files[path]);
+        print_file_change(repository, path.as_str(), old, new, max_size)?;
+    }
 
-            for change in diff.iter_all_changes() {
-                match change.tag() {
-                    ChangeTag::Delete => {
-                        cprint!("<red>-{}</red>", change);
-                    }
-                    ChangeTag::Insert => {
-                        cprint!("<green>-{}</green>", change);
-                    }
-                    ChangeTag::Equal => {
-                        cprint!("{}", change);
-                    }
-                };
-            }
-        }
+    Ok(())
+}
+
+/// Shows how a stored file changed, its mode and then its content.
+fn print_file_change(
+    repository: &Repository,
+    path: &str,
+    old: &FileEntry,
+    new: &FileEntry,
+    max_size: u64,
+) -> pit::Result<()> {
+    if old.mode != new.mode {
+        print_mode_change(path, old.mode, new.mode);
     }
+    if old.hash == new.hash {
+        return Ok(());
+    }
+    let old_size = repository.blob_size(old.hash.as_str())?;
+    let size = repository.blob_size(new.hash.as_str())?;
+    if size > max_size || old_size > max_size {
+        print_size_change(path, old_size, size);
+        return Ok(());
+    }
+    let old_content = repository.read_blob_content(old.hash.as_str())?;
+    let content = repository.read_blob_content(new.hash.as_str())?;
+    if old.mode == FileMode::Symlink && new.mode == FileMode::Symlink {
+        print_link_change(path, &old_content, &content);
+        return Ok(());
+    }
+    print_diff(path, &old_content, &content);
+
+    Ok(())
 }
 
-fn get_diff_between_trees(root: TreeNodeRef, second_tree: TreeNodeRef) {
-    if root.borrow().type_of_file == "tree" {
-        for child in &root.borrow().children {
-            let children = second_tree.borrow().children.clone();
-            let matched = children
-                .iter()
-                .find(|x| x.borrow().name == child.borrow().name);
-            match matched {
-                Some(node) => {
-                    get_diff_between_trees(child.clone(), node.clone());
-                }
-                None => {
-                    if child.borrow().type_of_file != "tree" {
-                        println!("{} was added", child.borrow().path);
-                    }
-                }
-            }
-        }
-    } else if root.borrow().hash != second_tree.borrow().hash {
-        let root_result = fs::read_to_string(root.borrow().pit_path.clone());
-        let second_result = fs::read_to_string(second_tree.borrow().pit_path.clone());
-        if root_result.is_err() {
-            println!("{} was modified", root.borrow().path);
-            return;
-        }
-        if second_result.is_err() {
-            println!("{} was modified", root.borrow().path);
-            return;
-        }
-        let content_root = root_result.unwrap();
-        let content_second = second_result.unwrap();
-        let segments_content_root: Vec<&str> = content_root.split("\n\n").collect();
-        let segments_content_second: Vec<&str> = content_second.split("\n\n").collect();
-        let text_root = &segments_content_root[..segments_content_root.len() - 3];
-        let text_second = &segments_content_second[..segments_content_second.len() - 3];
-        let text_file_root = text_root.join("\n\n");
-        let text_file_second = text_second.join("\n\n");
-        let diff = TextDiff::from_lines(text_file_second.as_str(), text_file_root.as_str());
+/// Files too large to diff are only described by their size.
+fn print_size_change(path: &str, old_size: u64, new_size: u64) {
+    println!(
+        "Large files differ: {} ({} bytes -> {} bytes)",
+        path, old_size, new_size
+    );
+}
 
-        for change in diff.iter_all_changes() {
-            match change.tag() {
-                ChangeTag::Delete => {
-                    cprint!("<red>-{}</red>", change);
-                }
-                ChangeTag::Insert => {
-                    cprint!("<green>-{}</green>", change);
-                }
-                ChangeTag::Equal => {
-                    cprint!("{}", change);
-                }
-            };
-        }
+fn print_mode_change(path: &str, old_mode: FileMode, new_mode: FileMode) {
+    println!("Mode changed: {} ({} -> {})", path, old_mode, new_mode);
+}
+
+/// Symbolic links hold a single path, shown whole instead of as a line diff.
+fn print_link_change(path: &str, old_target: &[u8], new_target: &[u8]) {
+    println!(
+        "Symbolic link changed: {} ({} -> {})",
+        path,
+        String::from_utf8_lossy(old_target),
+        String::from_utf8_lossy(new_target)
+    );
+}
+
+fn print_diff(path: &str, old: &[u8], new: &[u8]) {
+    if is_binary(old) || is_binary(new) {
+        println!("Binary files differ: {}", path);
+        return;
+    }
+    let (old, new) = (String::from_utf8_lossy(old), String::from_utf8_lossy(new));
+    let diff = TextDiff::from_lines(old.as_ref(), new.as_ref());
+
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Delete => {
+                cprint!("<red>-{}</red>", change);
+            }
+            ChangeTag::Insert => {
+                cprint!("<green>-{}</green>", change);
+            }
+            ChangeTag::Equal => {
+                cprint!("{}", change);
+            }
+        };
     }
 }
@@ -0,0 +1,47 @@
+use crate::command::{open_repository, Command};
+use crate::Parser;
+use std::process;
+
+#[derive(Parser, Debug, Clone)]
+pub struct FsckArgs {}
+
+#[derive(Debug)]
+pub struct FsckCommand {
+    _arguments: FsckArgs,
+}
+
+impl FsckCommand {
+    pub fn new(args: FsckArgs) -> Self {
+        FsckCommand { _arguments: args }
+    }
+}
+
+impl Command for FsckCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            process::exit(1);
+        };
+        let report = match repository.fsck() {
+            Ok(report) => report,
+            Err(err) => {
+                println!("Verification failed: {}", err);
+                process::exit(1);
+            }
+        };
+
+        for problem in &report.problems {
+            println!("{}", problem);
+        }
+        for (hash, kind) in &report.dangling {
+            println!("dangling {} {}", kind, hash);
+        }
+        println!(
+            "Checked {} objects, found {} problems",
+            report.objects,
+            report.problems.len()
+        );
+        if !report.is_ok() {
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::command::{open_repository, Command};
+use crate::Parser;
+use std::time::Duration;
+
+#[derive(Parser, Debug, Clone)]
+pub struct GcArgs {
+    /// Only report what would be removed.
+    #[arg(long)]
+    dry_run: bool,
+    /// Unreachable objects younger than this many days are kept.
+    #[arg(long, default_value_t = 14)]
+    grace_days: u64,
+}
+
+#[derive(Debug)]
+pub struct GcCommand {
+    arguments: GcArgs,
+}
+
+impl GcCommand {
+    pub fn new(args: GcArgs) -> Self {
+        GcCommand { arguments: args }
+    }
+}
+
+impl Command for GcCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            return;
+        };
+        // a grace period too long to count in seconds keeps every unreachable object.
+        let grace = Duration::from_secs(self.arguments.grace_days.saturating_mul(24 * 60 * 60));
+        let stats = match repository.gc(grace, self.arguments.dry_run) {
+            Ok(stats) => stats,
+            Err(err) => {
+                println!("Garbage collection failed: {}", err);
+                return;
+            }
+        };
+
+        if self.arguments.dry_run {
+            println!(
+                "Would remove {} unreachable objects and free {} bytes",
+                stats.pruned, stats.reclaimed
+            );
+        } else {
+            println!(
+                "Removed {} unreachable objects and freed {} bytes",
+                stats.pruned, stats.reclaimed
+            );
+        }
+        if stats.recent > 0 {
+            println!(
+                "Kept {} unreachable objects younger than {} days",
+                stats.recent, self.arguments.grace_days
+            );
+        }
+    }
+}
@@ -1,9 +1,7 @@
 use crate::command::Command;
 use crate::Parser;
-use std::fs;
-use std::fs::File;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use pit::Repository;
+use std::path::Path;
 use std::process::exit;
 
 #[derive(Parser, Debug, Clone)]
@@ -30,78 +28,13 @@ impl Command for InitializeCommand {
             path_string.push_str(&self.arguments.directory.clone().unwrap());
         }
 
-        let directory_path = Path::new(&path_string);
-
-        let path = Path::new(&path_string).join(".pit");
-        if !directory_path.is_dir() {
-            println!("The path provided for the pit file is not a folder");
-            return;
-        }
-        if path.exists() {
-            println!("Pit file already exist. No action done.");
-            return;
+        match Repository::init(Path::new(&path_string)) {
+            Ok(_) => println!("Pit file was successfully created!"),
+            Err(err @ pit::Error::Io(_)) => {
+                println!("Pit file cannot be created: {}", err);
+                exit(1);
+            }
+            Err(err) => println!("{}", err),
         }
-
-        create_pid_folder(path);
-
-        println!("Pit file was successfully created!");
-    }
-}
-
-fn create_pid_folder(path: PathBuf) {
-    let pit_folder_result = fs::create_dir(path.clone());
-
-    if pit_folder_result.is_err() {
-        println!("Pit file cannot be created: {:?}", pit_folder_result.err());
-        exit(1);
-    }
-
-    let pit_objects_path = path.to_str().unwrap().to_owned() + "/objects";
-    let pit_objects_folder = Path::new(&pit_objects_path);
-    let pit_objects_folder_result = fs::create_dir(pit_objects_folder);
-    if pit_objects_folder_result.is_err() {
-        println!(
-            "Pit object file cannot be created: {:?}",
-            pit_objects_folder_result.err()
-        );
-        exit(1);
-    }
-    let add_folder_path = pit_objects_path + "/info";
-    let add_folder_result = File::create(add_folder_path);
-    if add_folder_result.is_err() {
-        println!(
-            "Cache file cannot be created: {:?}",
-            add_folder_result.err()
-        );
-        exit(1);
     }
-
-    let pit_refs_path = path.to_str().unwrap().to_owned() + "/refs";
-
-    let pit_refs_folder = Path::new(&pit_refs_path);
-
-    let pit_refs_folder_result = fs::create_dir(pit_refs_folder);
-
-    if pit_refs_folder_result.is_err() {
-        println!(
-            "Pit refs file cannot be created: {:?}",
-            pit_refs_folder_result.err()
-        );
-        exit(1);
-    }
-
-    let pit_head_path = path.to_str().unwrap().to_owned() + "/HEAD";
-
-    let pit_head_file = Path::new(&pit_head_path);
-    let file_result = File::create(pit_head_file);
-
-    if file_result.is_err() {
-        println!("Pit head file cannot be created: {:?}", file_result.err());
-        exit(1);
-    }
-
-    file_result
-        .unwrap()
-        .write_all(b"refs/main")
-        .expect("No permissions");
 }
@@ -0,0 +1,172 @@
+use crate::command::{open_repository, Command};
+use clap::Parser;
+use pit::object::is_binary;
+use pit::rename::DEFAULT_RENAME_THRESHOLD;
+use pit::{FileChanges, LogEntry, Repository};
+use similar::{ChangeTag, TextDiff};
+
+/// Files larger than this are not read to count their changed lines.
+const MAX_COUNTED_SIZE: u64 = 10 * 1024 * 1024;
+
+#[derive(Parser, Debug, Clone)]
+pub struct LogArgs {
+    /// Branch or commit to start from, the current commit by default.
+    revision: Option<String>,
+    /// List the files each commit added, modified, deleted or renamed.
+    #[arg(long)]
+    stat: bool,
+    /// Files at least this similar (in percent) to a file of the parent are shown as
+    /// renamed or copied.
+    #[arg(long, default_value_t = DEFAULT_RENAME_THRESHOLD, value_parser = clap::value_parser!(u8).range(0..=100))]
+    find_renames: u8,
+}
+
+#[derive(Debug)]
+pub struct LogCommand {
+    arguments: LogArgs,
+}
+
+impl LogCommand {
+    pub fn new(args: LogArgs) -> Self {
+        LogCommand { arguments: args }
+    }
+}
+
+impl Command for LogCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            return;
+        };
+        let commit = match &self.arguments.revision {
+            Some(revision) => repository.resolve_revision(revision).map(Some),
+            None => repository.head_commit(),
+        };
+        let entries = match commit {
+            Ok(Some(commit)) => repository.log(commit.as_str()),
+            Ok(None) => {
+                println!("{}", pit::Error::NoCommits);
+                return;
+            }
+            Err(err) => Err(err),
+        };
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("Cannot read the history: {}", err);
+                return;
+            }
+        };
+
+        for entry in entries {
+            print_commit(&entry);
+            if !self.arguments.stat {
+                continue;
+            }
+            let stat = repository
+                .commit_changes(&entry.commit, self.arguments.find_renames)
+                .and_then(|changes| print_stat(&repository, &entry, &changes));
+            if let Err(err) = stat {
+                println!("Cannot compare the files of {}: {}", entry.hash, err);
+            }
+        }
+    }
+}
+
+fn print_commit(entry: &LogEntry) {
+    println!("commit {}", entry.hash);
+    if entry.commit.parents.len() > 1 {
+        println!("Merge: {}", entry.commit.parents.join(" "));
+    }
+    println!();
+    for line in entry.commit.message.lines() {
+        println!("    {}", line);
+    }
+    println!();
+}
+
+/// Lists the changed files of a commit with the number of lines each one gained and lost.
+fn print_stat(repository: &Repository, entry: &LogEntry, changes: &FileChanges) -> pit::Result<()> {
+    let parent = entry.commit.parents.first().map(|x| x.as_str());
+    let old_files = repository.read_commit_files(parent)?;
+    let new_files = repository.read_tree_files(entry.commit.tree.as_str())?;
+    let (mut insertions, mut deletions) = (0, 0);
+    let mut lines: Vec<(String, Option<(usize, usize)>)> = Vec::new();
+    for path in &changes.added {
+        let counts = count_lines(repository, None, Some(&new_files[path].hash))?;
+        lines.push(("added: ".to_string() + path, counts));
+    }
+    for path in &changes.modified {
+        let (old, new) = (&old_files[path].hash, &new_files[path].hash);
+        let counts = count_lines(repository, Some(old), Some(new))?;
+        lines.push(("modified: ".to_string() + path, counts));
+    }
+    for path in &changes.deleted {
+        let counts = count_lines(repository, Some(&old_files[path].hash), None)?;
+        lines.push(("deleted: ".to_string() + path, counts));
+    }
+    for rename in &changes.renamed {
+        let (old, new) = (&old_files[&rename.from].hash, &new_files[&rename.to].hash);
+        let counts = count_lines(repository, Some(old), Some(new))?;
+        lines.push((rename.to_string(), counts));
+    }
+
+    for (line, counts) in &lines {
+        match counts {
+            Some((0, 0)) => println!(" {}", line),
+            Some((0, removed)) => println!(" {} (-{})", line, removed),
+            Some((added, 0)) => println!(" {} (+{})", line, added),
+            Some((added, removed)) => println!(" {} (+{} -{})", line, added, removed),
+            None => println!(" {} (binary)", line),
+        }
+        if let Some((added, removed)) = counts {
+            insertions += added;
+            deletions += removed;
+        }
+    }
+    println!(
+        " {} files changed, {} insertions(+), {} deletions(-)\n",
+        lines.len(),
+        insertions,
+        deletions
+    );
+
+    Ok(())
+}
+
+/// Counts the lines added and removed between two blobs, None for binary or large files.
+fn count_lines(
+    repository: &Repository,
+    old: Option<&String>,
+    new: Option<&String>,
+) -> pit::Result<Option<(usize, usize)>> {
+    let mut contents: Vec<Vec<u8>> = Vec::new();
+    for hash in [old, new] {
+        let Some(hash) = hash else {
+            contents.push(Vec::new());
+            continue;
+        };
+        if repository.blob_size(hash)? > MAX_COUNTED_SIZE {
+            return Ok(None);
+        }
+        let content = repository.read_blob_content(hash)?;
+        if is_binary(&content) {
+            return Ok(None);
+        }
+        contents.push(content);
+    }
+    let (old, new) = (
+        String::from_utf8_lossy(&contents[0]),
+        String::from_utf8_lossy(&contents[1]),
+    );
+    let diff = TextDiff::from_lines(old.as_ref(), new.as_ref());
+    let (mut added, mut removed) = (0, 0);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+
+    Ok(Some((added, removed)))
+}
@@ -3,12 +3,20 @@ mod checkout_git;
 mod command;
 mod commit_git;
 mod diff;
+mod fsck_git;
+mod gc_git;
 mod init_git;
+mod log_git;
 mod merge;
+mod migrate_git;
+mod mv_git;
+mod repack_git;
+mod restore_git;
+mod rm_git;
 mod status_git;
 
 use crate::command::Command;
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -27,6 +35,14 @@ enum Commands {
     Checkout(checkout_git::CheckoutArgs),
     Diff(diff::DiffArgs),
     Merge(merge::MergeArgs),
+    Migrate(migrate_git::MigrateArgs),
+    Repack(repack_git::RepackArgs),
+    Gc(gc_git::GcArgs),
+    Fsck(fsck_git::FsckArgs),
+    Rm(rm_git::RmArgs),
+    Mv(mv_git::MvArgs),
+    Log(log_git::LogArgs),
+    Restore(restore_git::RestoreArgs),
 }
 
 fn main() {
@@ -61,5 +77,37 @@ fn main() {
             let mut x = merge::MergeCommand::new(args.clone());
             x.execute();
         }
+        Commands::Migrate(args) => {
+            let mut x = migrate_git::MigrateCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Repack(args) => {
+            let mut x = repack_git::RepackCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Gc(args) => {
+            let mut x = gc_git::GcCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Fsck(args) => {
+            let mut x = fsck_git::FsckCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Rm(args) => {
+            let mut x = rm_git::RmCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Mv(args) => {
+            let mut x = mv_git::MvCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Log(args) => {
+            let mut x = log_git::LogCommand::new(args.clone());
+            x.execute();
+        }
+        Commands::Restore(args) => {
+            let mut x = restore_git::RestoreCommand::new(args.clone());
+            x.execute();
+        }
     };
 }
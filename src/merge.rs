@@ -1,42 +1,25 @@
-use crate::command::Command;
+use crate::checkout_git::print_tree_changes;
+use crate::command::{open_repository, Command};
+use crate::status_git::print_local_changes;
 use crate::Parser;
-use std::cell::RefCell;
-use std::fs;
-use std::rc::Rc;
-
-#[derive(Debug)]
-enum Errors {
-    Error,
-}
+use pit::MergeOutcome;
 
 #[derive(Parser, Debug, Clone)]
 pub struct MergeArgs {
-    branch: String,
-}
-
-type TreeNodeRef = Rc<RefCell<TreeInfo>>;
-#[derive(Debug, PartialEq, Clone, Default)]
-struct TreeInfo {
-    path: String,
-    hash: String,
-    pit_path: String,
-    parent: Option<TreeNodeRef>,
-    name: String,
-    type_of_file: String,
-    children: Vec<TreeNodeRef>,
-}
-impl TreeInfo {
-    fn new(path: String, name: String, parent: Option<TreeNodeRef>) -> Self {
-        TreeInfo {
-            children: Vec::new(),
-            path,
-            pit_path: Default::default(),
-            parent,
-            name,
-            type_of_file: Default::default(),
-            hash: "".to_string(),
-        }
-    }
+    #[arg(required_unless_present_any = ["continue_merge", "abort"])]
+    branch: Option<String>,
+    /// Merge even if it overwrites uncommitted changes.
+    #[arg(long)]
+    force: bool,
+    /// Message of the merge commit.
+    #[arg(short, long)]
+    message: Option<String>,
+    /// Create the merge commit once the conflicts are resolved.
+    #[arg(long = "continue", conflicts_with_all = ["branch", "abort"])]
+    continue_merge: bool,
+    /// Stop the merge and restore the files of the current branch.
+    #[arg(long, conflicts_with = "branch")]
+    abort: bool,
 }
 
 #[derive(Debug)]
@@ -52,201 +35,59 @@ impl MergeCommand {
 
 impl Command for MergeCommand {
     fn execute(&mut self) {
-        let head_file_path = "./.pit/HEAD";
-        let head_result = fs::read_to_string(head_file_path);
-        if head_result.is_err() {
-            println!("Fatal error. Head file not present.");
-            return;
-        }
-        let refs_path = "./.pit/";
-        let main_path = head_result.unwrap();
-        let head_commit_result =
-            fs::read_to_string(refs_path.to_string() + main_path.clone().as_str());
-        if head_commit_result.is_err() {
-            println!(
-                "Commit for current branch not found {:?}",
-                head_commit_result
-            );
+        let Some(repository) = open_repository() else {
             return;
-        }
-        let head_commit = head_commit_result.unwrap();
-
-        let branch_to_be_merged = self.arguments.branch.clone();
-        let branch_commit_result =
-            fs::read_to_string("./.pit/refs/".to_string() + branch_to_be_merged.as_str());
-        if branch_commit_result.is_err() {
-            println!("Branch to be merged not found");
-            return;
-        }
-        let branch_to_commit = branch_commit_result.unwrap();
-        if branch_to_commit.is_empty() || head_commit.is_empty() {
-            println!("There are no commit on one branch");
-            return;
-        }
-        if branch_to_commit == head_commit {
-            println!("Nothing to be merged");
+        };
+        if self.arguments.continue_merge {
+            match repository.continue_merge() {
+                Ok(hash) => println!("Merged with commit: {}", hash),
+                Err(err) => println!("{}", err),
+            }
             return;
         }
-        let head_root = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-            "./".to_string(),
-            "./".to_string(),
-            None,
-        )));
-        head_root.borrow_mut().type_of_file = "commit".to_string();
-        head_root.borrow_mut().pit_path =
-            "./.pit/objects/".to_string() + head_commit.clone().as_str();
-        head_root.borrow_mut().hash = head_commit.clone();
-        let branch_root = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-            "./".to_string(),
-            "./".to_string(),
-            None,
-        )));
-        branch_root.borrow_mut().type_of_file = "commit".to_string();
-        branch_root.borrow_mut().pit_path =
-            "./.pit/objects/".to_string() + branch_to_commit.clone().as_str();
-        branch_root.borrow_mut().hash = branch_to_commit.clone();
-        let lca = find_lca_node(head_root, branch_root);
-        if let Ok(hash) = lca {
-            println!("{}", hash);
-            let _ = fs::write(refs_path.to_string() + main_path.as_str(), hash);
-            println!("Merge success");
-        }
-    }
-}
-
-fn find_lca_node(
-    mut head_root: TreeNodeRef,
-    mut branch_root: TreeNodeRef,
-) -> Result<String, Errors> {
-    let result = populate_all_commits(head_root.clone());
-    let mut mutable: TreeNodeRef;
-    match result {
-        Err(e) => {
-            return Err(e);
-        }
-        Ok(node) => {
-            mutable = node.clone();
-            head_root = get_root_node(mutable);
-        }
-    }
-
-    let result_branch_root = populate_all_commits(branch_root.clone());
-    match result_branch_root {
-        Err(e) => {
-            return Err(e);
-        }
-        Ok(node) => {
-            mutable = node.clone();
-            branch_root = get_root_node(mutable);
-        }
-    }
-    while branch_root.borrow().hash == head_root.borrow().hash {
-        let branch_children = branch_root.borrow().children.clone();
-        let next_branch_node = branch_children
-            .iter()
-            .find(|x| *x.borrow().type_of_file == *"commit");
-        let head_children = head_root.borrow().children.clone();
-        let next_head_node = head_children
-            .iter()
-            .find(|x| *x.borrow().type_of_file == *"commit");
-        if next_head_node.is_none() || next_branch_node.is_none() {
-            if next_branch_node.is_none() {
-                println!("No changes to be merged");
-                return Err(Errors::Error);
-            } else {
-                loop {
-                    let branch_children = branch_root.borrow().children.clone();
-                    let next_branch_node = branch_children
-                        .iter()
-                        .find(|x| *x.borrow().type_of_file == *"commit");
-                    if next_branch_node.is_none() {
-                        // create simple merge
-                        return Ok(branch_root.borrow().hash.clone());
-                    }
-                    let next = next_branch_node.unwrap();
-                    branch_root = next.clone();
+        if self.arguments.abort {
+            match repository.abort_merge() {
+                Ok(changes) => {
+                    print_tree_changes(&changes);
+                    println!("Merge aborted");
                 }
+                Err(err) => println!("{}", err),
             }
+            return;
         }
-        let next_node = next_head_node.unwrap();
-        let next_node_branch = next_branch_node.unwrap();
-        head_root = next_node.clone();
-        branch_root = next_node_branch.clone();
-    }
-    if branch_root.borrow().hash == head_root.borrow().hash {
-        return Err(Errors::Error);
-    }
 
-    println!("No simple merge can be done");
-    Err(Errors::Error)
-}
+        let branch = self.arguments.branch.clone().unwrap_or_default();
+        let message = self.arguments.message.as_deref();
+        let outcome = match repository.merge(branch.as_str(), message, self.arguments.force) {
+            Ok(outcome) => outcome,
+            Err(pit::Error::LocalChanges(paths)) => {
+                print_local_changes(&paths);
+                return;
+            }
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
 
-fn populate_all_commits(root: TreeNodeRef) -> Result<TreeNodeRef, Errors> {
-    let head_file_result = fs::read_to_string(root.borrow().pit_path.clone());
-    if head_file_result.is_err() {
-        println!("Can't read from commit file {:?}", head_file_result);
-        return Err(Errors::Error);
-    }
-    let head_file = head_file_result.unwrap();
-    let mut lines = head_file.lines(); // first line tree second line parent.
-    let tree_line = lines.next();
-    if let Some(tree) = tree_line {
-        let commit = tree.split(' ').last().unwrap();
-        let head_tree = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-            "./".to_string(),
-            "./".to_string(),
-            Some(root.clone()),
-        )));
-        head_tree.borrow_mut().hash = commit.clone().to_string();
-        head_tree.borrow_mut().pit_path = "./.pit/objects/".to_string() + commit.clone();
-        head_tree.borrow_mut().type_of_file = "tree".to_string();
-        root.borrow_mut().children.push(head_tree.clone());
-    } else {
-        println!(
-            "Commit file {} doesn't have a tree changes",
-            root.borrow().hash
-        );
-        return Err(Errors::Error);
-    }
-    let parent_line = lines.next();
-    if let Some(parent) = parent_line {
-        let is_parent = parent.split(' ').next();
-        if let Some(text) = is_parent {
-            if text != "parent" {
-                println!("Commit {} formatted wrong", root.borrow().hash);
-                return Err(Errors::Error);
+        match outcome {
+            MergeOutcome::UpToDate => println!("Already up to date"),
+            MergeOutcome::FastForward { commit, changes } => {
+                print_tree_changes(&changes);
+                println!("Fast-forward to {}", commit);
+            }
+            MergeOutcome::Merged { commit, changes } => {
+                print_tree_changes(&changes);
+                println!("Merged with commit: {}", commit);
+            }
+            MergeOutcome::Conflicts { paths, changes } => {
+                print_tree_changes(&changes);
+                println!("Automatic merge failed. Conflicts in:");
+                for path in paths {
+                    println!("  {}", path);
+                }
+                println!("Fix the conflicts and run pit merge --continue, or pit merge --abort.");
             }
-        } else {
-            println!("Commit {} formatted wrong", root.borrow().hash);
-            return Err(Errors::Error);
-        }
-        let commit = parent.split(' ').last().unwrap();
-        if commit.trim().is_empty() {
-            return Ok(root);
-        }
-        let head_parent = TreeNodeRef::new(RefCell::from(TreeInfo::new(
-            "./".to_string(),
-            "./".to_string(),
-            None,
-        )));
-        head_parent.borrow_mut().hash = commit.clone().to_string();
-        head_parent.borrow_mut().pit_path = "./.pit/objects/".to_string() + commit.clone();
-        head_parent.borrow_mut().type_of_file = "commit".to_string();
-        head_parent.borrow_mut().children.push(root.clone());
-        root.borrow_mut().parent = Some(head_parent.clone());
-        let result = populate_all_commits(head_parent.clone());
-        if result.is_err() {
-            return Err(Errors::Error);
         }
     }
-    Ok(root)
-}
-
-fn get_root_node(mut node: TreeNodeRef) -> TreeNodeRef {
-    while node.borrow().parent.is_some() {
-        let aux_node = node.clone();
-        node = aux_node.clone().borrow_mut().parent.clone().unwrap();
-    }
-
-    node
 }
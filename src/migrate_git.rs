@@ -0,0 +1,33 @@
+use crate::command::Command;
+use crate::Parser;
+use pit::Repository;
+use std::path::Path;
+
+#[derive(Parser, Debug, Clone)]
+pub struct MigrateArgs {}
+
+#[derive(Debug)]
+pub struct MigrateCommand {
+    _arguments: MigrateArgs,
+}
+
+impl MigrateCommand {
+    pub fn new(args: MigrateArgs) -> Self {
+        MigrateCommand { _arguments: args }
+    }
+}
+
+impl Command for MigrateCommand {
+    fn execute(&mut self) {
+        match Repository::migrate(Path::new("./")) {
+            Ok(migration) if migration.from == migration.to => {
+                println!("Repository already uses format {}", migration.to)
+            }
+            Ok(migration) => println!(
+                "Migrated {} objects from format {} to format {}",
+                migration.objects, migration.from, migration.to
+            ),
+            Err(err) => println!("Migration failed: {}", err),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::command::{open_repository, Command};
+use clap::Parser;
+
+#[derive(Parser, Debug, Clone)]
+pub struct MvArgs {
+    source: String,
+    destination: String,
+    /// Replace the destination even if it already exists.
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug)]
+pub struct MvCommand {
+    arguments: MvArgs,
+}
+
+impl MvCommand {
+    pub fn new(args: MvArgs) -> Self {
+        MvCommand { arguments: args }
+    }
+}
+
+impl Command for MvCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            return;
+        };
+
+        let arguments = &self.arguments;
+        match repository.move_files(&arguments.source, &arguments.destination, arguments.force) {
+            Ok(moves) => {
+                for (path, target) in moves {
+                    println!("{} -> {}", path, target);
+                }
+            }
+            Err(err @ pit::Error::AlreadyExists(_)) => {
+                println!("{}, use --force to replace it.", err);
+            }
+            Err(err) => println!("Cannot move files: {}", err),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::command::{open_repository, Command};
+use crate::Parser;
+
+#[derive(Parser, Debug, Clone)]
+pub struct RepackArgs {}
+
+#[derive(Debug)]
+pub struct RepackCommand {
+    _arguments: RepackArgs,
+}
+
+impl RepackCommand {
+    pub fn new(args: RepackArgs) -> Self {
+        RepackCommand { _arguments: args }
+    }
+}
+
+impl Command for RepackCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            return;
+        };
+        let stats = match repository.objects().repack() {
+            Ok(stats) => stats,
+            Err(err) => {
+                println!("Repack failed: {}", err);
+                return;
+            }
+        };
+
+        match stats.pack {
+            None => println!("Nothing to pack"),
+            Some(pack) => {
+                println!(
+                    "Packed {} objects ({} as deltas) into {}",
+                    stats.objects, stats.deltas, pack
+                );
+                println!(
+                    "Objects take {} bytes instead of {}",
+                    stats.size_after, stats.size_before
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::checkout_git::print_tree_changes;
+use crate::command::{open_repository, Command};
+use clap::Parser;
+
+#[derive(Parser, Debug, Clone)]
+pub struct RestoreArgs {
+    #[arg(required = true)]
+    paths: Vec<String>,
+    /// Branch or commit to take the files from. By default the working files come from the
+    /// index and the index entries from the last commit.
+    #[arg(long)]
+    source: Option<String>,
+    /// Restore the index entries, unstaging the files.
+    #[arg(long)]
+    staged: bool,
+    /// Restore the working files, the default without --staged.
+    #[arg(long)]
+    worktree: bool,
+}
+
+#[derive(Debug)]
+pub struct RestoreCommand {
+    arguments: RestoreArgs,
+}
+
+impl RestoreCommand {
+    pub fn new(args: RestoreArgs) -> Self {
+        RestoreCommand { arguments: args }
+    }
+}
+
+impl Command for RestoreCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            return;
+        };
+
+        let arguments = &self.arguments;
+        let worktree = arguments.worktree || !arguments.staged;
+        let source = arguments.source.as_deref();
+        match repository.restore(&arguments.paths, source, arguments.staged, worktree) {
+            Ok(result) => {
+                for path in result.not_found {
+                    println!("{} did not match any file", path);
+                }
+                for path in &result.staged {
+                    println!(" {} restored in the index", path);
+                }
+                if worktree && !result.changes.is_empty() {
+                    print_tree_changes(&result.changes);
+                }
+            }
+            Err(err @ pit::Error::RevisionNotFound(_)) => println!("{}", err),
+            Err(err) => println!("Cannot restore files: {}", err),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use crate::command::{open_repository, Command};
+use clap::Parser;
+
+#[derive(Parser, Debug, Clone)]
+pub struct RmArgs {
+    paths: Vec<String>,
+    /// Only stop tracking the files, keep them in the working directory.
+    #[arg(long)]
+    cached: bool,
+    /// Delete the files even if they have changes that are not committed.
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug)]
+pub struct RmCommand {
+    arguments: RmArgs,
+}
+
+impl RmCommand {
+    pub fn new(args: RmArgs) -> Self {
+        RmCommand { arguments: args }
+    }
+}
+
+impl Command for RmCommand {
+    fn execute(&mut self) {
+        let Some(repository) = open_repository() else {
+            return;
+        };
+
+        let arguments = &self.arguments;
+        match repository.remove(&arguments.paths, arguments.cached, arguments.force) {
+            Ok(result) => {
+                for path in result.removed {
+                    println!("rm {}", path);
+                }
+                for path in result.not_tracked {
+                    println!("{} is not tracked", path);
+                }
+            }
+            Err(err @ pit::Error::LocalChanges(_)) => {
+                println!("{}", err);
+                println!("Commit them first or use --force to delete them.");
+            }
+            Err(err) => println!("Cannot remove files: {}", err),
+        }
+    }
+}